@@ -0,0 +1,202 @@
+use crate::class::{LoxClass, LoxInstance};
+use crate::environment::Environment;
+use crate::interpreter::{Interpreter, RuntimeError, Unwind, Value};
+use crate::parser::FunctionDecl;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Anything that can be invoked with a list of arguments
+pub trait LoxCallable: fmt::Debug + fmt::Display {
+    // The number of arguments the callable expects
+    fn arity(&self) -> usize;
+
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        args: Vec<Value>,
+    ) -> Result<Value, RuntimeError>;
+}
+
+// A function declared in Lox, along with the scope it closes over
+pub struct LoxFunction {
+    declaration: Rc<FunctionDecl>,
+    closure: Rc<RefCell<Environment>>,
+    // Initializers always return the instance they were called on
+    is_initializer: bool,
+}
+
+impl LoxFunction {
+    pub fn new(
+        declaration: Rc<FunctionDecl>,
+        closure: Rc<RefCell<Environment>>,
+        is_initializer: bool,
+    ) -> Self {
+        LoxFunction {
+            declaration,
+            closure,
+            is_initializer,
+        }
+    }
+
+    // Create a copy of this method whose scope binds 'this' to the given instance
+    pub fn bind(&self, instance: Rc<RefCell<LoxInstance>>) -> LoxFunction {
+        let mut environment = Environment::with_enclosing(Rc::clone(&self.closure));
+        environment.define("this".to_string(), Value::Instance(instance));
+
+        LoxFunction::new(
+            Rc::clone(&self.declaration),
+            Rc::new(RefCell::new(environment)),
+            self.is_initializer,
+        )
+    }
+
+    fn name(&self) -> String {
+        self.declaration.name.str_val().unwrap_or_default()
+    }
+}
+
+impl LoxCallable for LoxFunction {
+    fn arity(&self) -> usize {
+        self.declaration.params.len()
+    }
+
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        args: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let mut environment = Environment::with_enclosing(Rc::clone(&self.closure));
+        for (param, arg) in self.declaration.params.iter().zip(args) {
+            environment.define(param.str_val().expect("Expected identifier"), arg);
+        }
+
+        match interpreter.execute_block(&self.declaration.body, environment) {
+            Ok(()) | Err(Unwind::Return(_)) if self.is_initializer => {
+                self.closure
+                    .borrow()
+                    .get("this")
+                    .map_err(|kind| kind.at(&self.declaration.name))
+            }
+            Ok(()) => Ok(Value::Nil),
+            Err(Unwind::Return(value)) => Ok(value),
+            Err(Unwind::Error(err)) => Err(err),
+        }
+    }
+}
+
+// Printing the closure would recurse through every function defined in it
+impl fmt::Debug for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LoxFunction({})", self.name())
+    }
+}
+
+impl fmt::Display for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<fn {}>", self.name())
+    }
+}
+
+// A function implemented in Rust and exposed to Lox code
+pub struct NativeFunction {
+    pub name: &'static str,
+    pub arity: usize,
+    pub func: fn(&[Value]) -> Result<Value, RuntimeError>,
+}
+
+impl LoxCallable for NativeFunction {
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(
+        self: Rc<Self>,
+        _interpreter: &mut Interpreter,
+        args: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        (self.func)(&args)
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NativeFunction({})", self.name)
+    }
+}
+
+impl fmt::Display for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+// The native functions defined in the global scope of every interpreter
+pub fn natives() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction {
+            name: "clock",
+            arity: 0,
+            func: clock,
+        },
+        NativeFunction {
+            name: "gcStats",
+            arity: 0,
+            func: gc_stats,
+        },
+    ]
+}
+
+// Return the number of seconds since the Unix epoch
+fn clock(_args: &[Value]) -> Result<Value, RuntimeError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    Ok(Value::Number(now.as_secs_f64()))
+}
+
+// Return a GcStats instance shaped like the VM's. Values here are reference counted and freed as
+// soon as they are dropped, so there is no collector and every number is zero.
+fn gc_stats(_args: &[Value]) -> Result<Value, RuntimeError> {
+    let zero = || Value::Number(0.0);
+    let live_objects = new_instance(
+        "LiveObjects",
+        [
+            "total",
+            "strings",
+            "functions",
+            "natives",
+            "closures",
+            "upvalues",
+            "classes",
+            "instances",
+            "boundMethods",
+        ]
+        .map(|name| (name, zero())),
+    );
+
+    Ok(new_instance(
+        "GcStats",
+        [
+            ("bytesAllocated", zero()),
+            ("liveObjects", live_objects),
+            ("collections", zero()),
+            ("totalPauseMs", zero()),
+            ("peakBytes", zero()),
+        ],
+    ))
+}
+
+// Make an instance of a new class with no methods
+fn new_instance<const N: usize>(class_name: &str, fields: [(&str, Value); N]) -> Value {
+    let class = LoxClass::new(class_name.to_string(), None, HashMap::new());
+    let mut instance = LoxInstance::new(Rc::new(class));
+    for (name, value) in fields {
+        instance.set(name.to_string(), value);
+    }
+
+    Value::Instance(Rc::new(RefCell::new(instance)))
+}
@@ -0,0 +1,229 @@
+use crate::heap::Value;
+use crate::lexer::Span;
+
+// The instructions of the bytecode VM. Each is a single byte, followed by its operands:
+// constant and slot indexes and argument counts are one byte, jump offsets two.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[repr(u8)]
+pub enum OpCode {
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal,
+    SetLocal,
+    GetGlobal,
+    DefineGlobal,
+    SetGlobal,
+    GetUpvalue,
+    SetUpvalue,
+    GetProperty,
+    SetProperty,
+    GetSuper,
+    Equal,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Jump,
+    JumpIfFalse,
+    Loop,
+    Call,
+    Invoke,
+    SuperInvoke,
+    Closure,
+    CloseUpvalue,
+    Return,
+    Class,
+    Inherit,
+    Method,
+}
+
+impl OpCode {
+    // Every opcode, in the order of their byte values
+    const ALL: [OpCode; 39] = [
+        OpCode::Constant,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::DefineGlobal,
+        OpCode::SetGlobal,
+        OpCode::GetUpvalue,
+        OpCode::SetUpvalue,
+        OpCode::GetProperty,
+        OpCode::SetProperty,
+        OpCode::GetSuper,
+        OpCode::Equal,
+        OpCode::Greater,
+        OpCode::GreaterEqual,
+        OpCode::Less,
+        OpCode::LessEqual,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::Loop,
+        OpCode::Call,
+        OpCode::Invoke,
+        OpCode::SuperInvoke,
+        OpCode::Closure,
+        OpCode::CloseUpvalue,
+        OpCode::Return,
+        OpCode::Class,
+        OpCode::Inherit,
+        OpCode::Method,
+    ];
+
+    // How many bytes of operands follow the opcode. A closure is also followed by a pair of
+    // bytes for each variable it captures.
+    pub fn operand_len(self) -> usize {
+        match self {
+            OpCode::Jump
+            | OpCode::JumpIfFalse
+            | OpCode::Loop
+            | OpCode::Invoke
+            | OpCode::SuperInvoke => 2,
+            OpCode::Constant
+            | OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::GetProperty
+            | OpCode::SetProperty
+            | OpCode::GetSuper
+            | OpCode::Call
+            | OpCode::Closure
+            | OpCode::Class
+            | OpCode::Method => 1,
+            _ => 0,
+        }
+    }
+}
+
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, u8> {
+        OpCode::ALL.get(byte as usize).copied().ok_or(byte)
+    }
+}
+
+// A compiled function body: its bytecode, the constants it refers to, and where in the
+// source each instruction came from
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Value>,
+    // Run-length encoded spans: the span of every byte from each offset up to the next one
+    spans: Vec<(usize, Span)>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Chunk::default()
+    }
+
+    // Put a chunk back together, as it was when it was compiled
+    pub fn from_parts(code: Vec<u8>, constants: Vec<Value>, spans: Vec<(usize, Span)>) -> Self {
+        Chunk {
+            code,
+            constants,
+            spans,
+        }
+    }
+
+    pub fn write(&mut self, byte: u8, span: Span) {
+        if self.spans.last().is_none_or(|&(_, last)| last != span) {
+            self.spans.push((self.code.len(), span));
+        }
+        self.code.push(byte);
+    }
+
+    // Add a constant, reusing an equal one if there is one, and return its index
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        match self
+            .constants
+            .iter()
+            .position(|&constant| constant == value)
+        {
+            Some(index) => index,
+            None => {
+                self.constants.push(value);
+                self.constants.len() - 1
+            }
+        }
+    }
+
+    pub fn spans(&self) -> &[(usize, Span)] {
+        &self.spans
+    }
+
+    // The span of the source the byte at an offset was compiled from
+    pub fn span_at(&self, offset: usize) -> Span {
+        let run = self.spans.partition_point(|&(start, _)| start <= offset);
+        self.spans[run.saturating_sub(1)].1
+    }
+}
+
+#[cfg(test)]
+mod test_chunk {
+    use super::*;
+
+    #[test]
+    fn test_opcode_bytes() {
+        for (byte, op) in OpCode::ALL.iter().enumerate() {
+            assert_eq!(byte, *op as usize);
+            assert_eq!(Ok(*op), OpCode::try_from(byte as u8));
+        }
+        assert_eq!(Err(200), OpCode::try_from(200));
+    }
+
+    #[test]
+    fn test_spans() {
+        let span = |line| Span {
+            line,
+            ..Span::default()
+        };
+
+        let mut chunk = Chunk::new();
+        chunk.write(OpCode::Nil as u8, span(1));
+        chunk.write(OpCode::Constant as u8, span(2));
+        chunk.write(0, span(2));
+        chunk.write(OpCode::Return as u8, span(4));
+
+        // Consecutive bytes from the same source share an entry
+        assert_eq!(3, chunk.spans.len());
+        let lines: Vec<usize> = (0..4).map(|offset| chunk.span_at(offset).line).collect();
+        assert_eq!(vec![1, 2, 2, 4], lines);
+    }
+
+    #[test]
+    fn test_constants() {
+        let mut chunk = Chunk::new();
+        assert_eq!(0, chunk.add_constant(Value::Number(1.0)));
+        assert_eq!(1, chunk.add_constant(Value::Bool(true)));
+        // Equal constants are only stored once
+        assert_eq!(0, chunk.add_constant(Value::Number(1.0)));
+        assert_eq!(2, chunk.constants.len());
+    }
+}
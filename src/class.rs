@@ -0,0 +1,105 @@
+use crate::callable::{LoxCallable, LoxFunction};
+use crate::interpreter::{Interpreter, RuntimeError, RuntimeErrorKind, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+// A class declared in Lox; calling it constructs a new instance
+#[derive(Debug)]
+pub struct LoxClass {
+    pub name: String,
+    superclass: Option<Rc<LoxClass>>,
+    methods: HashMap<String, Rc<LoxFunction>>,
+}
+
+impl LoxClass {
+    pub fn new(
+        name: String,
+        superclass: Option<Rc<LoxClass>>,
+        methods: HashMap<String, Rc<LoxFunction>>,
+    ) -> Self {
+        LoxClass {
+            name,
+            superclass,
+            methods,
+        }
+    }
+
+    // Look up a method on this class, falling back to its superclasses
+    pub fn find_method(&self, name: &str) -> Option<Rc<LoxFunction>> {
+        match self.methods.get(name) {
+            Some(method) => Some(Rc::clone(method)),
+            None => self
+                .superclass
+                .as_ref()
+                .and_then(|superclass| superclass.find_method(name)),
+        }
+    }
+}
+
+impl LoxCallable for LoxClass {
+    // A class takes the same arguments as its initializer
+    fn arity(&self) -> usize {
+        self.find_method("init").map_or(0, |init| init.arity())
+    }
+
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        args: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let instance = Rc::new(RefCell::new(LoxInstance::new(Rc::clone(&self))));
+
+        if let Some(init) = self.find_method("init") {
+            Rc::new(init.bind(Rc::clone(&instance))).call(interpreter, args)?;
+        }
+
+        Ok(Value::Instance(instance))
+    }
+}
+
+impl fmt::Display for LoxClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+// An instance of a class, holding its own fields
+#[derive(Debug)]
+pub struct LoxInstance {
+    class: Rc<LoxClass>,
+    fields: HashMap<String, Value>,
+}
+
+impl LoxInstance {
+    pub fn new(class: Rc<LoxClass>) -> Self {
+        LoxInstance {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+
+    // Look up a property; fields shadow methods, and methods are bound to the instance
+    pub fn get(instance: &Rc<RefCell<LoxInstance>>, name: &str) -> Result<Value, RuntimeErrorKind> {
+        if let Some(value) = instance.borrow().fields.get(name) {
+            return Ok(value.clone());
+        }
+
+        let method = instance.borrow().class.find_method(name);
+        match method {
+            Some(method) => Ok(Value::Callable(Rc::new(method.bind(Rc::clone(instance))))),
+            None => Err(RuntimeErrorKind::UndefinedProperty(name.to_string())),
+        }
+    }
+
+    pub fn set(&mut self, name: String, value: Value) {
+        self.fields.insert(name, value);
+    }
+}
+
+impl fmt::Display for LoxInstance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
@@ -0,0 +1,694 @@
+use crate::chunk::OpCode;
+use crate::heap::{Function, Heap, Obj, ObjRef, Value};
+use crate::interpreter::CompileError;
+use crate::lexer::{KwordType, LitType, OpType, Span, Token};
+use crate::parser::{Expr, FunctionDecl, Stmt, StmtVisitor, Visitor};
+use std::fmt;
+
+// Limits of the bytecode format, whose operands are single bytes
+const MAX_LOCALS: usize = 256;
+const MAX_UPVALUES: usize = 256;
+const MAX_CONSTANTS: usize = 256;
+
+// The kinds of error found compiling to bytecode, all of them programs too big for its limits
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BytecodeErrorKind {
+    TooManyConstants,
+    TooManyLocals,
+    TooManyUpvalues,
+    JumpTooLarge,
+    LoopTooLarge,
+}
+
+impl fmt::Display for BytecodeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BytecodeErrorKind::TooManyConstants => write!(f, "Too many constants in one chunk."),
+            BytecodeErrorKind::TooManyLocals => write!(f, "Too many local variables in function."),
+            BytecodeErrorKind::TooManyUpvalues => {
+                write!(f, "Too many closure variables in function.")
+            }
+            BytecodeErrorKind::JumpTooLarge => write!(f, "Too much code to jump over."),
+            BytecodeErrorKind::LoopTooLarge => write!(f, "Loop body too large."),
+        }
+    }
+}
+
+// An error compiling to bytecode, along with the code that caused it
+#[derive(Debug, PartialEq)]
+pub struct BytecodeError {
+    pub kind: BytecodeErrorKind,
+    pub span: Span,
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+// Compile a resolved program into the function for its top-level script
+pub fn compile(stmts: &[Stmt], heap: &mut Heap) -> Result<ObjRef, Vec<CompileError>> {
+    let mut compiler = Compiler {
+        heap,
+        functions: Vec::new(),
+        classes: Vec::new(),
+        span: Span::default(),
+        errors: Vec::new(),
+    };
+
+    compiler.begin_function(None, FunctionKind::Script);
+    for stmt in stmts {
+        compiler.visit_stmt(stmt);
+    }
+    let (function, _) = compiler.end_function();
+
+    if compiler.errors.is_empty() {
+        Ok(compiler.heap.alloc(Obj::Function(function)))
+    } else {
+        Err(compiler.errors)
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    // Whether a closure captures it, so it has to be moved to the heap when its scope ends
+    is_captured: bool,
+}
+
+// Where a closure finds a captured variable: a local slot of the enclosing function, or one
+// of the enclosing function's own upvalues
+#[derive(PartialEq, Clone, Copy)]
+struct UpvalueRef {
+    index: u8,
+    is_local: bool,
+}
+
+// A function being compiled
+struct FunctionState {
+    function: Function,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueRef>,
+    scope_depth: usize,
+}
+
+// A class whose methods are being compiled
+struct ClassState {
+    has_superclass: bool,
+}
+
+// Compiles the AST into bytecode. The resolver has already rejected invalid programs, so the
+// only errors left are programs too big for the bytecode's limits.
+struct Compiler<'h> {
+    heap: &'h mut Heap,
+    // The functions being compiled, innermost last
+    functions: Vec<FunctionState>,
+    classes: Vec<ClassState>,
+    // Where the code being compiled came from, recorded for each instruction
+    span: Span,
+    errors: Vec<CompileError>,
+}
+
+impl Compiler<'_> {
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn error(&mut self, kind: BytecodeErrorKind) {
+        self.errors.push(CompileError::Bytecode(BytecodeError {
+            kind,
+            span: self.span,
+        }));
+    }
+
+    fn begin_function(&mut self, name: Option<&str>, kind: FunctionKind) {
+        let name = name.map(|name| self.heap.intern(name));
+        // Slot zero holds the function being called, or the instance for methods
+        let receiver = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+
+        self.functions.push(FunctionState {
+            function: Function {
+                name,
+                ..Function::default()
+            },
+            kind,
+            locals: vec![Local {
+                name: receiver.to_string(),
+                depth: 0,
+                is_captured: false,
+            }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+        });
+    }
+
+    fn end_function(&mut self) -> (Function, Vec<UpvalueRef>) {
+        self.emit_return();
+        let state = self.functions.pop().unwrap();
+        let mut function = state.function;
+        function.upvalue_count = state.upvalues.len();
+        (function, state.upvalues)
+    }
+
+    fn emit(&mut self, byte: u8) {
+        let span = self.span;
+        self.current().function.chunk.write(byte, span);
+    }
+
+    fn emit_op(&mut self, op: OpCode) {
+        self.emit(op as u8);
+    }
+
+    fn emit_with(&mut self, op: OpCode, operand: u8) {
+        self.emit_op(op);
+        self.emit(operand);
+    }
+
+    fn emit_return(&mut self) {
+        if self.current().kind == FunctionKind::Initializer {
+            self.emit_with(OpCode::GetLocal, 0);
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.emit_op(OpCode::Return);
+    }
+
+    fn make_constant(&mut self, value: Value) -> u8 {
+        let index = self.current().function.chunk.add_constant(value);
+        if index >= MAX_CONSTANTS {
+            self.error(BytecodeErrorKind::TooManyConstants);
+            return 0;
+        }
+        index as u8
+    }
+
+    fn emit_constant(&mut self, value: Value) {
+        let index = self.make_constant(value);
+        self.emit_with(OpCode::Constant, index);
+    }
+
+    fn identifier_constant(&mut self, name: &str) -> u8 {
+        let string = self.heap.intern(name);
+        self.make_constant(Value::Obj(string))
+    }
+
+    // Emit a jump with a placeholder offset, returning where to patch it
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_op(op);
+        self.emit(0xff);
+        self.emit(0xff);
+        self.current().function.chunk.code.len() - 2
+    }
+
+    // Point a jump at the next instruction to be emitted
+    fn patch_jump(&mut self, offset: usize) {
+        let code = &mut self.current().function.chunk.code;
+        let jump = code.len() - offset - 2;
+        if jump > u16::MAX as usize {
+            self.error(BytecodeErrorKind::JumpTooLarge);
+            return;
+        }
+
+        code[offset..offset + 2].copy_from_slice(&(jump as u16).to_be_bytes());
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_op(OpCode::Loop);
+        let offset = self.current().function.chunk.code.len() - loop_start + 2;
+        if offset > u16::MAX as usize {
+            self.error(BytecodeErrorKind::LoopTooLarge);
+        }
+
+        let [high, low] = (offset.min(u16::MAX as usize) as u16).to_be_bytes();
+        self.emit(high);
+        self.emit(low);
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let state = self.current();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+
+        while let Some(local) = self.current().locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+
+            if local.is_captured {
+                self.emit_op(OpCode::CloseUpvalue);
+            } else {
+                self.emit_op(OpCode::Pop);
+            }
+            self.current().locals.pop();
+        }
+    }
+
+    fn add_local(&mut self, name: &str) {
+        if self.current().locals.len() == MAX_LOCALS {
+            self.error(BytecodeErrorKind::TooManyLocals);
+            return;
+        }
+
+        let depth = self.current().scope_depth;
+        self.current().locals.push(Local {
+            name: name.to_string(),
+            depth,
+            is_captured: false,
+        });
+    }
+
+    // Bind the value on top of the stack to a variable: a new local slot inside a scope, or a
+    // global at the top level
+    fn define_variable(&mut self, name: &str) {
+        if self.current().scope_depth > 0 {
+            self.add_local(name);
+        } else {
+            let constant = self.identifier_constant(name);
+            self.emit_with(OpCode::DefineGlobal, constant);
+        }
+    }
+
+    fn resolve_local(&self, function: usize, name: &str) -> Option<u8> {
+        self.functions[function]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name)
+            .map(|slot| slot as u8)
+    }
+
+    fn resolve_upvalue(&mut self, function: usize, name: &str) -> Option<u8> {
+        if function == 0 {
+            return None;
+        }
+
+        let enclosing = function - 1;
+        if let Some(slot) = self.resolve_local(enclosing, name) {
+            self.functions[enclosing].locals[slot as usize].is_captured = true;
+            return Some(self.add_upvalue(function, slot, true));
+        }
+
+        let index = self.resolve_upvalue(enclosing, name)?;
+        Some(self.add_upvalue(function, index, false))
+    }
+
+    fn add_upvalue(&mut self, function: usize, index: u8, is_local: bool) -> u8 {
+        let upvalue = UpvalueRef { index, is_local };
+        let upvalues = &mut self.functions[function].upvalues;
+        if let Some(existing) = upvalues.iter().position(|&u| u == upvalue) {
+            return existing as u8;
+        }
+
+        if upvalues.len() == MAX_UPVALUES {
+            self.error(BytecodeErrorKind::TooManyUpvalues);
+            return 0;
+        }
+        upvalues.push(upvalue);
+        (upvalues.len() - 1) as u8
+    }
+
+    // Emit a read of a variable, or a write of the value on top of the stack to it
+    fn named_variable(&mut self, name: &str, assign: bool) {
+        let function = self.functions.len() - 1;
+        let (get, set, operand) = if let Some(slot) = self.resolve_local(function, name) {
+            (OpCode::GetLocal, OpCode::SetLocal, slot)
+        } else if let Some(index) = self.resolve_upvalue(function, name) {
+            (OpCode::GetUpvalue, OpCode::SetUpvalue, index)
+        } else {
+            let constant = self.identifier_constant(name);
+            (OpCode::GetGlobal, OpCode::SetGlobal, constant)
+        };
+
+        self.emit_with(if assign { set } else { get }, operand);
+    }
+
+    // Compile a function and emit the closure that creates it at runtime
+    fn function(&mut self, decl: &FunctionDecl, kind: FunctionKind) {
+        self.span = decl.name.span;
+        self.begin_function(Some(&decl.name.lexeme), kind);
+        self.begin_scope();
+        for param in &decl.params {
+            self.add_local(&param.lexeme);
+        }
+        self.current().function.arity = decl.params.len();
+        for stmt in &decl.body {
+            self.visit_stmt(stmt);
+        }
+
+        let (function, upvalues) = self.end_function();
+        let function = self.heap.alloc(Obj::Function(function));
+        let constant = self.make_constant(Value::Obj(function));
+        self.span = decl.name.span;
+        self.emit_with(OpCode::Closure, constant);
+        for upvalue in upvalues {
+            self.emit(upvalue.is_local as u8);
+            self.emit(upvalue.index);
+        }
+    }
+
+    fn call_args(&mut self, args: &[Expr], paren: &Token) -> u8 {
+        for arg in args {
+            self.visit_expr(arg);
+        }
+        self.span = paren.span;
+        // The parser already rejects calls with too many arguments
+        args.len() as u8
+    }
+}
+
+impl Visitor<()> for Compiler<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Assign(name, value, _) => {
+                self.visit_expr(value);
+                self.span = name.span;
+                self.named_variable(&name.lexeme, true);
+            }
+            Expr::Bin(left, op, right) => {
+                self.visit_expr(left);
+                self.visit_expr(right);
+                self.span = op.span;
+                match op.op_type() {
+                    Some(OpType::Plus) => self.emit_op(OpCode::Add),
+                    Some(OpType::Minus) => self.emit_op(OpCode::Subtract),
+                    Some(OpType::Star) => self.emit_op(OpCode::Multiply),
+                    Some(OpType::Slash) => self.emit_op(OpCode::Divide),
+                    Some(OpType::EqualEqual) => self.emit_op(OpCode::Equal),
+                    Some(OpType::BangEqual) => {
+                        self.emit_op(OpCode::Equal);
+                        self.emit_op(OpCode::Not);
+                    }
+                    Some(OpType::Greater) => self.emit_op(OpCode::Greater),
+                    Some(OpType::GreaterEqual) => self.emit_op(OpCode::GreaterEqual),
+                    Some(OpType::Less) => self.emit_op(OpCode::Less),
+                    Some(OpType::LessEqual) => self.emit_op(OpCode::LessEqual),
+                    _ => unreachable!(
+                        "The parser only builds binary expressions from binary operators"
+                    ),
+                }
+            }
+            Expr::Call(callee, paren, args) => match callee.as_ref() {
+                // Calling a method directly skips creating a bound method
+                Expr::Get(object, name) => {
+                    self.visit_expr(object);
+                    let argc = self.call_args(args, paren);
+                    let constant = self.identifier_constant(&name.lexeme);
+                    self.emit_with(OpCode::Invoke, constant);
+                    self.emit(argc);
+                }
+                Expr::Super(_, method, _) => {
+                    self.named_variable("this", false);
+                    let argc = self.call_args(args, paren);
+                    self.named_variable("super", false);
+                    let constant = self.identifier_constant(&method.lexeme);
+                    self.emit_with(OpCode::SuperInvoke, constant);
+                    self.emit(argc);
+                }
+                _ => {
+                    self.visit_expr(callee);
+                    let argc = self.call_args(args, paren);
+                    self.emit_with(OpCode::Call, argc);
+                }
+            },
+            Expr::Get(object, name) => {
+                self.visit_expr(object);
+                self.span = name.span;
+                let constant = self.identifier_constant(&name.lexeme);
+                self.emit_with(OpCode::GetProperty, constant);
+            }
+            Expr::Grouping(expr) => self.visit_expr(expr),
+            Expr::Lit(lit, span) => {
+                self.span = *span;
+                match lit {
+                    LitType::Number(n) => self.emit_constant(Value::Number(*n)),
+                    LitType::String(s) => {
+                        let string = self.heap.intern(s);
+                        self.emit_constant(Value::Obj(string));
+                    }
+                    LitType::True => self.emit_op(OpCode::True),
+                    LitType::False => self.emit_op(OpCode::False),
+                    LitType::Nil => self.emit_op(OpCode::Nil),
+                    LitType::Identifier(_) => unreachable!("Identifiers are not literal values"),
+                }
+            }
+            Expr::Logical(left, kword, right) => {
+                self.visit_expr(left);
+                // The left operand is the result if it decides the outcome
+                let end_jump = if *kword == KwordType::And {
+                    self.emit_jump(OpCode::JumpIfFalse)
+                } else {
+                    let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                    let end_jump = self.emit_jump(OpCode::Jump);
+                    self.patch_jump(else_jump);
+                    end_jump
+                };
+                self.emit_op(OpCode::Pop);
+                self.visit_expr(right);
+                self.patch_jump(end_jump);
+            }
+            Expr::Set(object, name, value) => {
+                self.visit_expr(object);
+                self.visit_expr(value);
+                self.span = name.span;
+                let constant = self.identifier_constant(&name.lexeme);
+                self.emit_with(OpCode::SetProperty, constant);
+            }
+            Expr::Super(keyword, method, _) => {
+                self.span = keyword.span;
+                self.named_variable("this", false);
+                self.named_variable("super", false);
+                self.span = method.span;
+                let constant = self.identifier_constant(&method.lexeme);
+                self.emit_with(OpCode::GetSuper, constant);
+            }
+            Expr::This(keyword, _) => {
+                self.span = keyword.span;
+                self.named_variable("this", false);
+            }
+            Expr::Unary(op, operand) => {
+                self.visit_expr(operand);
+                self.span = op.span;
+                match op.op_type() {
+                    Some(OpType::Minus) => self.emit_op(OpCode::Negate),
+                    Some(OpType::Bang) => self.emit_op(OpCode::Not),
+                    _ => unreachable!("The parser only builds unary expressions from '-' and '!'"),
+                }
+            }
+            Expr::Variable(name, _) => {
+                self.span = name.span;
+                self.named_variable(&name.lexeme, false);
+            }
+        }
+    }
+}
+
+impl StmtVisitor<()> for Compiler<'_> {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Block(stmts) => {
+                self.begin_scope();
+                for stmt in stmts {
+                    self.visit_stmt(stmt);
+                }
+                self.end_scope();
+            }
+            Stmt::Class(decl) => {
+                self.span = decl.name.span;
+                let name = decl.name.lexeme.as_str();
+                let constant = self.identifier_constant(name);
+                self.emit_with(OpCode::Class, constant);
+                self.define_variable(name);
+                self.classes.push(ClassState {
+                    has_superclass: false,
+                });
+
+                if let Some(superclass) = &decl.superclass {
+                    self.visit_expr(superclass);
+                    let superclass_span = self.span;
+                    // Methods find their superclass in a scope of its own around the class body
+                    self.begin_scope();
+                    self.add_local("super");
+                    self.named_variable(name, false);
+                    self.span = superclass_span;
+                    self.emit_op(OpCode::Inherit);
+                    self.classes.last_mut().unwrap().has_superclass = true;
+                }
+
+                // Keep the class on the stack while its methods are attached
+                self.span = decl.name.span;
+                self.named_variable(name, false);
+                for method in &decl.methods {
+                    let kind = if method.name.lexeme == "init" {
+                        FunctionKind::Initializer
+                    } else {
+                        FunctionKind::Method
+                    };
+                    self.function(method, kind);
+                    let constant = self.identifier_constant(&method.name.lexeme);
+                    self.emit_with(OpCode::Method, constant);
+                }
+                self.emit_op(OpCode::Pop);
+
+                if self.classes.pop().unwrap().has_superclass {
+                    self.end_scope();
+                }
+            }
+            Stmt::Expression(expr) => {
+                self.visit_expr(expr);
+                self.emit_op(OpCode::Pop);
+            }
+            Stmt::Function(decl) => {
+                // A local function is in scope in its own body so that it can recurse
+                if self.current().scope_depth > 0 {
+                    self.add_local(&decl.name.lexeme);
+                    self.function(decl, FunctionKind::Function);
+                } else {
+                    self.function(decl, FunctionKind::Function);
+                    self.define_variable(&decl.name.lexeme);
+                }
+            }
+            Stmt::If(condition, then_branch, else_branch) => {
+                self.visit_expr(condition);
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                self.visit_stmt(then_branch);
+
+                let else_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(then_jump);
+                self.emit_op(OpCode::Pop);
+                if let Some(else_branch) = else_branch {
+                    self.visit_stmt(else_branch);
+                }
+                self.patch_jump(else_jump);
+            }
+            Stmt::Print(expr) => {
+                self.visit_expr(expr);
+                self.emit_op(OpCode::Print);
+            }
+            Stmt::Return(keyword, value) => {
+                self.span = keyword.span;
+                match value {
+                    Some(value) => {
+                        self.visit_expr(value);
+                        self.emit_op(OpCode::Return);
+                    }
+                    None => self.emit_return(),
+                }
+            }
+            Stmt::Var(name, initializer) => {
+                match initializer {
+                    Some(initializer) => self.visit_expr(initializer),
+                    None => self.emit_op(OpCode::Nil),
+                }
+                self.span = name.span;
+                self.define_variable(&name.lexeme);
+            }
+            Stmt::While(condition, body) => {
+                let loop_start = self.current().function.chunk.code.len();
+                self.visit_expr(condition);
+                let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                self.visit_stmt(body);
+                self.emit_loop(loop_start);
+                self.patch_jump(exit_jump);
+                self.emit_op(OpCode::Pop);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_compiler {
+    use super::*;
+    use crate::interpreter;
+
+    fn compile_src(heap: &mut Heap, src: &str) -> Result<ObjRef, Vec<CompileError>> {
+        let (stmts, _) = interpreter::compile(src).unwrap();
+        compile(&stmts, heap)
+    }
+
+    #[test]
+    fn test_compile_expression() {
+        let mut heap = Heap::new();
+        let script = compile_src(&mut heap, "print 1 + 2 * 3;").unwrap();
+        let chunk = &heap.function(script).chunk;
+
+        let op = |op: OpCode| op as u8;
+        assert_eq!(
+            vec![
+                op(OpCode::Constant),
+                0,
+                op(OpCode::Constant),
+                1,
+                op(OpCode::Constant),
+                2,
+                op(OpCode::Multiply),
+                op(OpCode::Add),
+                op(OpCode::Print),
+                op(OpCode::Nil),
+                op(OpCode::Return),
+            ],
+            chunk.code
+        );
+        assert_eq!(
+            vec![Value::Number(1.0), Value::Number(2.0), Value::Number(3.0)],
+            chunk.constants
+        );
+    }
+
+    #[test]
+    fn test_locals_and_upvalues() {
+        let mut heap = Heap::new();
+        let src = "{ var a = 1; fun f() { return a; } }";
+        let script = compile_src(&mut heap, src).unwrap();
+        let chunk = &heap.function(script).chunk;
+
+        // 'f' captures 'a', which is closed over rather than popped when the block ends
+        let closure = chunk
+            .code
+            .iter()
+            .position(|&byte| byte == OpCode::Closure as u8)
+            .unwrap();
+        assert_eq!(&[1, 1], &chunk.code[closure + 2..closure + 4]);
+        assert_eq!(
+            &[OpCode::Pop as u8, OpCode::CloseUpvalue as u8],
+            &chunk.code[closure + 4..closure + 6]
+        );
+
+        let Value::Obj(f) = chunk.constants[1] else {
+            panic!("Expected the function as a constant");
+        };
+        assert_eq!(1, heap.function(f).upvalue_count);
+    }
+
+    #[test]
+    fn test_too_many_constants() {
+        let mut heap = Heap::new();
+        let src: String = (0..300).map(|i| format!("print v{};\n", i)).collect();
+
+        let errs = compile_src(&mut heap, &src).unwrap_err();
+        match &errs[0] {
+            CompileError::Bytecode(err) => {
+                assert_eq!(BytecodeErrorKind::TooManyConstants, err.kind);
+                assert_eq!(257, err.span.line);
+            }
+            other => panic!("Expected a bytecode error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use crate::compiler::BytecodeErrorKind;
+use crate::interpreter::{CompileError, LoxError, RuntimeError, RuntimeErrorKind};
+use crate::lexer::{LexErrorKind, Span};
+use crate::resolver::ResolveErrorKind;
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::str::FromStr;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+
+// How errors are written out
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ErrorFormat {
+    // rustc-style, with the offending source underlined
+    Human,
+    // One line per error, in the reference implementation's format
+    Short,
+    // One JSON object per line, for tools to consume
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(ErrorFormat::Human),
+            "short" => Ok(ErrorFormat::Short),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(format!("Unknown error format '{}'.", s)),
+        }
+    }
+}
+
+// An error ready to be shown to the user, with a code identifying what kind it is
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+    // A suggestion for how to fix the error
+    pub help: Option<&'static str>,
+}
+
+impl From<&CompileError> for Diagnostic {
+    fn from(err: &CompileError) -> Self {
+        let (code, help) = match err {
+            CompileError::Lex(err) => match err.kind {
+                LexErrorKind::UnexpectedCharacter => ("E0001", None),
+                LexErrorKind::UnterminatedString => {
+                    ("E0002", Some("strings must be closed with a '\"'"))
+                }
+            },
+            // Syntax errors are told apart by their messages alone
+            CompileError::Parse(_) => ("E0100", None),
+            CompileError::Resolve(err) => match err.kind {
+                ResolveErrorKind::ReadInOwnInitializer => (
+                    "E0201",
+                    Some("give the new variable a different name to read the outer one"),
+                ),
+                ResolveErrorKind::AlreadyDeclared => (
+                    "E0202",
+                    Some("assign to the existing variable instead of declaring it again"),
+                ),
+                ResolveErrorKind::TopLevelReturn => ("E0203", None),
+                ResolveErrorKind::ReturnFromInitializer => (
+                    "E0204",
+                    Some("initializers always return 'this'; use 'return;' to leave early"),
+                ),
+                ResolveErrorKind::ThisOutsideClass => ("E0205", None),
+                ResolveErrorKind::SuperOutsideClass => ("E0206", None),
+                ResolveErrorKind::SuperWithoutSuperclass => (
+                    "E0207",
+                    Some("declare a superclass with 'class Name < Superclass'"),
+                ),
+                ResolveErrorKind::InheritFromSelf => ("E0208", None),
+            },
+            CompileError::Bytecode(err) => match err.kind {
+                BytecodeErrorKind::TooManyConstants => ("E0401", None),
+                BytecodeErrorKind::TooManyLocals => ("E0402", None),
+                BytecodeErrorKind::TooManyUpvalues => ("E0403", None),
+                BytecodeErrorKind::JumpTooLarge => ("E0404", None),
+                BytecodeErrorKind::LoopTooLarge => ("E0405", None),
+            },
+        };
+
+        let span = match err {
+            CompileError::Lex(err) => err.span,
+            CompileError::Parse(err) => err.token.span,
+            CompileError::Resolve(err) => err.token.span,
+            CompileError::Bytecode(err) => err.span,
+        };
+        let message = match err {
+            CompileError::Lex(err) => err.to_string(),
+            CompileError::Parse(err) => err.to_string(),
+            CompileError::Resolve(err) => err.to_string(),
+            CompileError::Bytecode(err) => err.to_string(),
+        };
+
+        Diagnostic {
+            code,
+            message,
+            span,
+            help,
+        }
+    }
+}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(err: &RuntimeError) -> Self {
+        let (code, help) = match err.kind {
+            RuntimeErrorKind::OperandMustBeNumber => ("E0301", None),
+            RuntimeErrorKind::OperandsMustBeNumbers => ("E0302", None),
+            RuntimeErrorKind::OperandsMustBeNumbersOrStrings => ("E0303", None),
+            RuntimeErrorKind::UndefinedVariable(_) => {
+                ("E0304", Some("declare it with 'var' before using it"))
+            }
+            RuntimeErrorKind::NotCallable => ("E0305", None),
+            RuntimeErrorKind::WrongArity { .. } => ("E0306", None),
+            RuntimeErrorKind::OnlyInstancesHaveProperties => ("E0307", None),
+            RuntimeErrorKind::OnlyInstancesHaveFields => ("E0308", None),
+            RuntimeErrorKind::UndefinedProperty(_) => ("E0309", None),
+            RuntimeErrorKind::SuperclassMustBeClass => ("E0310", None),
+            RuntimeErrorKind::ThisOutsideClass => ("E0311", None),
+            RuntimeErrorKind::SuperOutsideClass => ("E0312", None),
+            RuntimeErrorKind::StackOverflow => (
+                "E0313",
+                Some("check for recursion that never reaches a base case"),
+            ),
+            RuntimeErrorKind::MalformedBytecode => {
+                ("E0314", Some("recompile the script from source"))
+            }
+        };
+
+        Diagnostic {
+            code,
+            message: err.to_string(),
+            span: err.span,
+            help,
+        }
+    }
+}
+
+// Every diagnostic for an error, in the order they were found
+pub fn diagnostics(err: &LoxError) -> Vec<Diagnostic> {
+    match err {
+        LoxError::Compile(errs) => errs.iter().map(Diagnostic::from).collect(),
+        LoxError::Runtime(err) => vec![Diagnostic::from(err)],
+    }
+}
+
+// Write an error to stderr; the file name and source are used to show where it happened
+pub fn report(err: &LoxError, format: ErrorFormat, file: &str, src: &str) {
+    let mut stderr = io::stderr().lock();
+
+    let _ = match format {
+        ErrorFormat::Short => writeln!(stderr, "{}", err),
+        ErrorFormat::Json => diagnostics(err)
+            .iter()
+            .try_for_each(|d| writeln!(stderr, "{}", render_json(d, file, src))),
+        ErrorFormat::Human => {
+            let color = stderr.is_terminal() && env::var_os("NO_COLOR").is_none();
+            diagnostics(err)
+                .iter()
+                .try_for_each(|d| write!(stderr, "{}", render(d, file, src, color)))
+        }
+    };
+}
+
+// Render a diagnostic the way rustc does, underlining its span in the source
+pub fn render(diagnostic: &Diagnostic, file: &str, src: &str, color: bool) -> String {
+    let paint = |style: &'static str| if color { style } else { "" };
+    let (reset, bold, red, blue) = (paint(RESET), paint(BOLD), paint(RED), paint(BLUE));
+
+    let span = diagnostic.span;
+    let line = src.lines().nth(span.line.saturating_sub(1)).unwrap_or("");
+    let gutter = " ".repeat(span.line.to_string().len());
+
+    // Keep tabs so that the underline lines up with the source however tabs are shown
+    let indent: String = line
+        .chars()
+        .take(span.column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    // Only the first line of a span that runs over several lines is underlined
+    let width = src
+        .get(span.byte_offset..span.byte_offset + span.len)
+        .and_then(|text| text.lines().next())
+        .map_or(0, |text| text.chars().count())
+        .max(1);
+
+    let mut out = format!(
+        "{red}error[{}]{reset}{bold}: {}{reset}\n\
+         {gutter}{blue}-->{reset} {}:{}:{}\n",
+        diagnostic.code, diagnostic.message, file, span.line, span.column,
+    );
+    // Compiled scripts have no source to show
+    if !src.is_empty() {
+        out += &format!(
+            "{blue}{gutter} |{reset}\n\
+             {blue}{} |{reset} {}\n\
+             {blue}{gutter} |{reset} {}{red}{}{reset}\n",
+            span.line,
+            line,
+            indent,
+            "^".repeat(width),
+        );
+    }
+
+    if let Some(help) = diagnostic.help {
+        out += &format!(
+            "{blue}{gutter} |{reset}\n\
+             {blue}{gutter} ={reset} {bold}help{reset}: {}\n",
+            help
+        );
+    }
+
+    out + "\n"
+}
+
+// Render a diagnostic as a single line of JSON. Lines and columns count from 1, and the
+// end is the position just past the last character of the span.
+pub fn render_json(diagnostic: &Diagnostic, file: &str, src: &str) -> String {
+    let span = diagnostic.span;
+    let (end_line, end_column) = end_position(span, src);
+    let notes = diagnostic
+        .help
+        .iter()
+        .map(|help| format!("{{\"kind\":\"help\",\"message\":{}}}", json_string(help)))
+        .collect::<Vec<String>>()
+        .join(",");
+
+    // rlox only ever reports errors
+    format!(
+        "{{\"severity\":\"error\",\"code\":{},\"message\":{},\"file\":{},\
+         \"start\":{{\"line\":{},\"column\":{}}},\"end\":{{\"line\":{},\"column\":{}}},\
+         \"notes\":[{}]}}",
+        json_string(diagnostic.code),
+        json_string(&diagnostic.message),
+        json_string(file),
+        span.line,
+        span.column,
+        end_line,
+        end_column,
+        notes
+    )
+}
+
+// Find the line and column just past the end of a span
+fn end_position(span: Span, src: &str) -> (usize, usize) {
+    let Some(text) = src.get(span.byte_offset..span.byte_offset + span.len) else {
+        return (span.line, span.column + span.len);
+    };
+
+    text.chars()
+        .fold((span.line, span.column), |(line, column), c| match c {
+            '\n' => (line + 1, 1),
+            _ => (line, column + 1),
+        })
+}
+
+// Quote a string for JSON, escaping anything that can't appear in it as is
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod test_diagnostics {
+    use super::*;
+    use crate::interpreter::Interpreter;
+
+    fn render_src(src: &str) -> String {
+        let err = Interpreter::new().run(src).unwrap_err();
+        diagnostics(&err)
+            .iter()
+            .map(|d| render(d, "test.lox", src, false))
+            .collect()
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            "error[E0302]: Operands must be numbers.\n \
+             --> test.lox:2:11\n  \
+             |\n\
+             2 |   print 1 < \"a\";\n  \
+             |           ^\n\n",
+            render_src("var a;\n  print 1 < \"a\";")
+        );
+
+        // Help notes follow the snippet, and wide spans are underlined in full
+        assert_eq!(
+            "error[E0304]: Undefined variable 'missing'.\n \
+             --> test.lox:1:7\n  \
+             |\n\
+             1 | print missing;\n  \
+             |       ^^^^^^^\n  \
+             |\n  \
+             = help: declare it with 'var' before using it\n\n",
+            render_src("print missing;")
+        );
+    }
+
+    #[test]
+    fn test_render_edges() {
+        // Errors at the end of the file point just past the last character
+        assert!(render_src("print 1").contains("1 | print 1\n  |        ^\n"));
+
+        // Tabs are kept so that the caret lines up, and the gutter widens with the line number
+        let src = format!("{}\tprint -nil;", "\n".repeat(9));
+        assert!(render_src(&src).contains("10 | \tprint -nil;\n   | \t      ^\n"));
+
+        // Without the source, only the location is shown
+        let err = Interpreter::new().run("print -nil;").unwrap_err();
+        assert_eq!(
+            "error[E0301]: Operand must be a number.\n \
+             --> test.loxc:1:7\n\n",
+            render(&diagnostics(&err)[0], "test.loxc", "", false)
+        );
+
+        // Every compile error gets its own diagnostic
+        assert_eq!(
+            2,
+            render_src("print ;\nvar 1;")
+                .matches("error[E0100]")
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_error_format() {
+        assert_eq!(Ok(ErrorFormat::Human), "human".parse());
+        assert_eq!(Ok(ErrorFormat::Short), "short".parse());
+        assert_eq!(Ok(ErrorFormat::Json), "json".parse());
+        assert!("xml".parse::<ErrorFormat>().is_err());
+    }
+
+    #[test]
+    fn test_render_json() {
+        let src = "print missing;";
+        let err = Interpreter::new().run(src).unwrap_err();
+
+        assert_eq!(
+            "{\"severity\":\"error\",\"code\":\"E0304\",\
+             \"message\":\"Undefined variable 'missing'.\",\"file\":\"dir\\\\\\\"q\\\".lox\",\
+             \"start\":{\"line\":1,\"column\":7},\"end\":{\"line\":1,\"column\":14},\
+             \"notes\":[{\"kind\":\"help\",\"message\":\"declare it with 'var' before using it\"}]}",
+            render_json(&diagnostics(&err)[0], "dir\\\"q\".lox", src)
+        );
+
+        // Spans running over several lines end on a later line
+        let src = "var a;\n\"one\ntwo";
+        let err = Interpreter::new().run(src).unwrap_err();
+        let json = render_json(&diagnostics(&err)[0], "test.lox", src);
+        assert!(
+            json.contains("\"start\":{\"line\":2,\"column\":1},\"end\":{\"line\":3,\"column\":4}")
+        );
+        assert!(json.contains("\"notes\":[{\"kind\":\"help\""));
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!("\"plain\"", json_string("plain"));
+        assert_eq!("\"a\\\"b\\\\c\\nd\\u0007\"", json_string("a\"b\\c\nd\u{7}"));
+        assert_eq!("\"¶\"", json_string("¶"));
+    }
+}
@@ -0,0 +1,234 @@
+use crate::chunk::{Chunk, OpCode};
+use crate::heap::{Heap, Obj, ObjRef, Value};
+use std::fmt::Write;
+
+// Disassemble a compiled script along with every function nested in it, each function after
+// the one it was declared in
+pub fn disassemble(script: ObjRef, heap: &Heap) -> String {
+    let mut out = String::new();
+    let mut functions = vec![script];
+
+    while let Some(function) = functions.pop() {
+        let chunk = &heap.function(function).chunk;
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&disassemble_chunk(
+            chunk,
+            &heap.format(Value::Obj(function)),
+            heap,
+        ));
+
+        // Pushed in reverse so that they come out in the order they were declared
+        for constant in chunk.constants.iter().rev() {
+            if let Value::Obj(obj) = *constant {
+                if matches!(heap.get(obj), Obj::Function(_)) {
+                    functions.push(obj);
+                }
+            }
+        }
+    }
+
+    out
+}
+
+// Disassemble every instruction in a chunk under a header with its name
+pub fn disassemble_chunk(chunk: &Chunk, name: &str, heap: &Heap) -> String {
+    let mut out = format!("== {} ==\n", name);
+    let mut offset = 0;
+
+    while offset < chunk.code.len() {
+        let (instruction, next) = disassemble_instruction(chunk, offset, heap);
+        out.push_str(&instruction);
+        out.push('\n');
+        offset = next;
+    }
+
+    out
+}
+
+// Disassemble the instruction at an offset, returning it along with the offset of the next
+// one. The source line is left out if it is the same as the previous instruction's.
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize, heap: &Heap) -> (String, usize) {
+    let line = chunk.span_at(offset).line;
+    let mut out = if offset > 0 && line == chunk.span_at(offset - 1).line {
+        format!("{:04}    | ", offset)
+    } else {
+        format!("{:04} {:4} ", offset, line)
+    };
+
+    let byte = chunk.code[offset];
+    let Ok(op) = OpCode::try_from(byte) else {
+        write!(out, "Unknown opcode {}", byte).unwrap();
+        return (out, offset + 1);
+    };
+
+    let name = op_name(op);
+    let operand = |i: usize| chunk.code[offset + i];
+    let constant = |i: usize| heap.format(chunk.constants[operand(i) as usize]);
+    let mut next = offset + 1 + op.operand_len();
+
+    match op {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::GetSuper
+        | OpCode::Class
+        | OpCode::Method => {
+            write!(out, "{:<16} {:4} '{}'", name, operand(1), constant(1)).unwrap();
+        }
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Call => {
+            write!(out, "{:<16} {:4}", name, operand(1)).unwrap();
+        }
+        OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+            let jump = u16::from_be_bytes([operand(1), operand(2)]) as usize;
+            let target = if op == OpCode::Loop {
+                next - jump
+            } else {
+                next + jump
+            };
+            write!(out, "{:<16} {:4} -> {}", name, offset, target).unwrap();
+        }
+        OpCode::Invoke | OpCode::SuperInvoke => {
+            write!(
+                out,
+                "{:<16} ({} args) {:4} '{}'",
+                name,
+                operand(2),
+                operand(1),
+                constant(1)
+            )
+            .unwrap();
+        }
+        OpCode::Closure => {
+            write!(out, "{:<16} {:4} {}", name, operand(1), constant(1)).unwrap();
+
+            // Each captured variable follows as a pair of bytes: whether it is a local of the
+            // enclosing function, and its index there
+            let Value::Obj(function) = chunk.constants[operand(1) as usize] else {
+                unreachable!("Closures are only made from function constants")
+            };
+            let upvalue_count = heap.function(function).upvalue_count;
+            for _ in 0..upvalue_count {
+                let at = next;
+                let kind = if chunk.code[at] == 1 {
+                    "local"
+                } else {
+                    "upvalue"
+                };
+                write!(
+                    out,
+                    "\n{:04}    |                     {} {}",
+                    at,
+                    kind,
+                    chunk.code[at + 1]
+                )
+                .unwrap();
+                next += 2;
+            }
+        }
+        _ => out.push_str(&name),
+    }
+
+    (out, next)
+}
+
+// The name of an opcode in the reference implementation's style, e.g. OP_JUMP_IF_FALSE
+fn op_name(op: OpCode) -> String {
+    let mut name = "OP".to_string();
+    for c in format!("{:?}", op).chars() {
+        if c.is_ascii_uppercase() {
+            name.push('_');
+        }
+        name.push(c.to_ascii_uppercase());
+    }
+    name
+}
+
+#[cfg(test)]
+mod test_disassembler {
+    use super::*;
+    use crate::compiler;
+    use crate::interpreter;
+
+    fn compile(heap: &mut Heap, src: &str) -> ObjRef {
+        let (stmts, _) = interpreter::compile(src).unwrap();
+        compiler::compile(&stmts, heap).unwrap()
+    }
+
+    #[test]
+    fn test_op_name() {
+        assert_eq!("OP_CONSTANT", op_name(OpCode::Constant));
+        assert_eq!("OP_JUMP_IF_FALSE", op_name(OpCode::JumpIfFalse));
+    }
+
+    #[test]
+    fn test_disassemble_chunk() {
+        let mut heap = Heap::new();
+        let script = compile(&mut heap, "var a = 1;\nwhile (a < 3)\n  a = a + 1;");
+        let chunk = &heap.function(script).chunk;
+
+        assert_eq!(
+            "== script ==\n\
+             0000    1 OP_CONSTANT         0 '1'\n\
+             0002    | OP_DEFINE_GLOBAL    1 'a'\n\
+             0004    2 OP_GET_GLOBAL       1 'a'\n\
+             0006    | OP_CONSTANT         2 '3'\n\
+             0008    | OP_LESS\n\
+             0009    | OP_JUMP_IF_FALSE    9 -> 24\n\
+             0012    | OP_POP\n\
+             0013    3 OP_GET_GLOBAL       1 'a'\n\
+             0015    | OP_CONSTANT         0 '1'\n\
+             0017    | OP_ADD\n\
+             0018    | OP_SET_GLOBAL       1 'a'\n\
+             0020    | OP_POP\n\
+             0021    | OP_LOOP            21 -> 4\n\
+             0024    | OP_POP\n\
+             0025    | OP_NIL\n\
+             0026    | OP_RETURN\n",
+            disassemble_chunk(chunk, "script", &heap)
+        );
+    }
+
+    #[test]
+    fn test_disassemble_functions() {
+        let mut heap = Heap::new();
+        let src = "fun outer() {\n  var x = 1;\n  fun inner() { return x; }\n  return inner;\n}\nouter()();";
+        let script = compile(&mut heap, src);
+
+        assert_eq!(
+            "== <script> ==\n\
+             0000    1 OP_CLOSURE          0 <fn outer>\n\
+             0002    | OP_DEFINE_GLOBAL    1 'outer'\n\
+             0004    6 OP_GET_GLOBAL       1 'outer'\n\
+             0006    | OP_CALL             0\n\
+             0008    | OP_CALL             0\n\
+             0010    | OP_POP\n\
+             0011    | OP_NIL\n\
+             0012    | OP_RETURN\n\
+             \n\
+             == <fn outer> ==\n\
+             0000    2 OP_CONSTANT         0 '1'\n\
+             0002    3 OP_CLOSURE          1 <fn inner>\n\
+             0004    |                     local 1\n\
+             0006    4 OP_GET_LOCAL        2\n\
+             0008    | OP_RETURN\n\
+             0009    | OP_NIL\n\
+             0010    | OP_RETURN\n\
+             \n\
+             == <fn inner> ==\n\
+             0000    3 OP_GET_UPVALUE      0\n\
+             0002    | OP_RETURN\n\
+             0003    | OP_NIL\n\
+             0004    | OP_RETURN\n",
+            disassemble(script, &heap)
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use crate::interpreter::{RuntimeErrorKind, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// A scope of variable bindings, linked to the scope that encloses it
+#[derive(Debug, Default)]
+pub struct Environment {
+    values: HashMap<String, Value>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Environment::default()
+    }
+
+    // Create a scope nested inside the given one
+    pub fn with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
+        Environment {
+            values: HashMap::new(),
+            enclosing: Some(enclosing),
+        }
+    }
+
+    // Bind a name in this scope, replacing any existing binding of the same name
+    pub fn define(&mut self, name: String, value: Value) {
+        self.values.insert(name, value);
+    }
+
+    // Look up a variable, searching outward through the enclosing scopes
+    pub fn get(&self, name: &str) -> Result<Value, RuntimeErrorKind> {
+        if let Some(value) = self.values.get(name) {
+            return Ok(value.clone());
+        }
+
+        match self.enclosing {
+            Some(ref enclosing) => enclosing.borrow().get(name),
+            None => Err(RuntimeErrorKind::UndefinedVariable(name.to_string())),
+        }
+    }
+
+    // Return the scope the given number of hops outward from this one
+    pub fn ancestor(env: &Rc<RefCell<Environment>>, distance: usize) -> Rc<RefCell<Environment>> {
+        let mut env = Rc::clone(env);
+        for _ in 0..distance {
+            let enclosing = Rc::clone(
+                env.borrow()
+                    .enclosing
+                    .as_ref()
+                    .expect("Resolved scope distance is out of range"),
+            );
+            env = enclosing;
+        }
+
+        env
+    }
+
+    // Look up a variable in the scope exactly the given number of hops outward
+    pub fn get_at(
+        env: &Rc<RefCell<Environment>>,
+        distance: usize,
+        name: &str,
+    ) -> Result<Value, RuntimeErrorKind> {
+        Environment::ancestor(env, distance)
+            .borrow()
+            .values
+            .get(name)
+            .cloned()
+            .ok_or_else(|| RuntimeErrorKind::UndefinedVariable(name.to_string()))
+    }
+
+    // Reassign a variable in the scope exactly the given number of hops outward
+    pub fn assign_at(
+        env: &Rc<RefCell<Environment>>,
+        distance: usize,
+        name: &str,
+        value: Value,
+    ) -> Result<(), RuntimeErrorKind> {
+        match Environment::ancestor(env, distance)
+            .borrow_mut()
+            .values
+            .get_mut(name)
+        {
+            Some(var) => {
+                *var = value;
+                Ok(())
+            }
+            None => Err(RuntimeErrorKind::UndefinedVariable(name.to_string())),
+        }
+    }
+
+    // Reassign an existing variable in the nearest scope that declares it
+    pub fn assign(&mut self, name: &str, value: Value) -> Result<(), RuntimeErrorKind> {
+        if let Some(var) = self.values.get_mut(name) {
+            *var = value;
+            return Ok(());
+        }
+
+        match self.enclosing {
+            Some(ref enclosing) => enclosing.borrow_mut().assign(name, value),
+            None => Err(RuntimeErrorKind::UndefinedVariable(name.to_string())),
+        }
+    }
+
+    // The bindings in this scope, not counting enclosing ones
+    pub fn bindings(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.values.iter()
+    }
+}
+
+#[cfg(test)]
+mod test_environment {
+    use super::*;
+
+    #[test]
+    fn test_shadowing_and_assignment() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        globals
+            .borrow_mut()
+            .define("a".to_string(), Value::Number(1.0));
+        globals
+            .borrow_mut()
+            .define("b".to_string(), Value::Number(2.0));
+
+        let mut inner = Environment::with_enclosing(Rc::clone(&globals));
+        inner.define("a".to_string(), Value::Bool(true));
+
+        // The inner binding shadows the outer one
+        assert_eq!(Ok(Value::Bool(true)), inner.get("a"));
+
+        // Assignment walks outward to the scope that declares the variable
+        inner.assign("b", Value::Nil).unwrap();
+        assert_eq!(Ok(Value::Nil), globals.borrow().get("b"));
+        assert_eq!(Ok(Value::Number(1.0)), globals.borrow().get("a"));
+    }
+
+    #[test]
+    fn test_at_distance() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        globals
+            .borrow_mut()
+            .define("a".to_string(), Value::Number(1.0));
+        let inner = Rc::new(RefCell::new(Environment::with_enclosing(Rc::clone(
+            &globals,
+        ))));
+        inner.borrow_mut().define("a".to_string(), Value::Nil);
+
+        // Lookups at a distance skip the shadowing inner binding
+        assert_eq!(Ok(Value::Number(1.0)), Environment::get_at(&inner, 1, "a"));
+        Environment::assign_at(&inner, 1, "a", Value::Bool(true)).unwrap();
+        assert_eq!(Ok(Value::Bool(true)), globals.borrow().get("a"));
+        assert_eq!(Ok(Value::Nil), Environment::get_at(&inner, 0, "a"));
+    }
+
+    #[test]
+    fn test_undefined() {
+        let mut env = Environment::new();
+
+        assert_eq!(
+            Err(RuntimeErrorKind::UndefinedVariable("x".to_string())),
+            env.get("x")
+        );
+        assert_eq!(
+            Err(RuntimeErrorKind::UndefinedVariable("x".to_string())),
+            env.assign("x", Value::Nil)
+        );
+    }
+}
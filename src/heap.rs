@@ -0,0 +1,635 @@
+use crate::chunk::Chunk;
+use std::collections::HashMap;
+use std::mem;
+use std::time::{Duration, Instant};
+
+// The heap size that first triggers a collection, and how much it may grow relative to what
+// survived a collection before the next one
+const INITIAL_GC_THRESHOLD: usize = 1024 * 1024;
+const GC_GROWTH_FACTOR: usize = 2;
+
+// A handle to an object on the heap
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct ObjRef(u32);
+
+// A value of the bytecode VM. Anything bigger than a number lives on the heap, and because
+// strings are interned, equal strings are the same object.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Obj(ObjRef),
+}
+
+impl Value {
+    // Lox treats nil and false as falsey and everything else as truthy
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+}
+
+#[derive(Debug)]
+pub enum Obj {
+    String(Box<str>),
+    Function(Function),
+    Native(Native),
+    Closure(Closure),
+    Upvalue(Upvalue),
+    Class(Class),
+    Instance(Instance),
+    BoundMethod(BoundMethod),
+}
+
+impl Obj {
+    // Roughly how many bytes an object takes up, including what it owns
+    fn size(&self) -> usize {
+        let owned = match self {
+            Obj::String(s) => s.len(),
+            Obj::Function(function) => {
+                let chunk = &function.chunk;
+                mem::size_of_val(chunk.code.as_slice())
+                    + mem::size_of_val(chunk.constants.as_slice())
+                    + mem::size_of_val(chunk.spans())
+            }
+            Obj::Closure(closure) => closure.upvalues.len() * mem::size_of::<ObjRef>(),
+            Obj::Class(class) => class.methods.len() * mem::size_of::<(ObjRef, ObjRef)>(),
+            Obj::Instance(instance) => instance.fields.len() * mem::size_of::<(ObjRef, Value)>(),
+            Obj::Native(_) | Obj::Upvalue(_) | Obj::BoundMethod(_) => 0,
+        };
+        mem::size_of::<Obj>() + owned
+    }
+}
+
+// A compiled function; the top-level script has no name
+#[derive(Debug, Default)]
+pub struct Function {
+    pub name: Option<ObjRef>,
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+}
+
+// A function implemented in Rust and exposed to Lox code. It is given the heap to allocate
+// its result on, which never collects while it runs.
+#[derive(Debug)]
+pub struct Native {
+    pub name: &'static str,
+    pub arity: usize,
+    pub function: fn(&mut Heap, &[Value]) -> Value,
+}
+
+// A function along with the variables it captured
+#[derive(Debug)]
+pub struct Closure {
+    pub function: ObjRef,
+    pub upvalues: Vec<ObjRef>,
+}
+
+// A captured variable: still on the stack while its scope is running, and moved onto the heap
+// once the scope ends
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+#[derive(Debug)]
+pub struct Class {
+    pub name: ObjRef,
+    // Method closures by name
+    pub methods: HashMap<ObjRef, ObjRef>,
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: ObjRef,
+    pub fields: HashMap<ObjRef, Value>,
+}
+
+// A method closure bound to the instance it was accessed on
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: ObjRef,
+}
+
+// How many objects of each kind are allocated
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ObjectCounts {
+    pub strings: usize,
+    pub functions: usize,
+    pub natives: usize,
+    pub closures: usize,
+    pub upvalues: usize,
+    pub classes: usize,
+    pub instances: usize,
+    pub bound_methods: usize,
+}
+
+impl ObjectCounts {
+    pub fn total(&self) -> usize {
+        self.strings
+            + self.functions
+            + self.natives
+            + self.closures
+            + self.upvalues
+            + self.classes
+            + self.instances
+            + self.bound_methods
+    }
+}
+
+// A snapshot of the heap and of the collector's work so far. Sizes are the heap's estimates,
+// and objects that became garbage since the last collection still count as live.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GcStats {
+    pub bytes_allocated: usize,
+    pub live_objects: ObjectCounts,
+    pub collections: usize,
+    pub total_pause: Duration,
+    pub peak_bytes: usize,
+}
+
+// Where the VM's objects live. Objects are addressed by handles rather than pointers, and
+// strings are interned so that each distinct string is allocated once.
+//
+// Memory is managed by a tri-color mark-and-sweep collector. collect() has the owner of the
+// heap mark the roots, which turns them grey, then blackens grey objects by marking everything
+// they refer to until none are left. Objects still white are unreachable and get freed, and
+// their slots are reused by later allocations.
+#[derive(Debug)]
+pub struct Heap {
+    // Freed slots are None until they are reused
+    objects: Vec<Option<Obj>>,
+    marks: Vec<bool>,
+    free: Vec<u32>,
+    // Objects that have been marked but whose references haven't been yet
+    grey: Vec<ObjRef>,
+    // The intern table holds its strings weakly, so unused strings can be freed
+    strings: HashMap<Box<str>, ObjRef>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    // Collect at every opportunity, to flush out objects that aren't rooted when they should be
+    stress: bool,
+    // Print a line to stderr after each collection
+    log: bool,
+    peak_bytes: usize,
+    collections: usize,
+    total_pause: Duration,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            objects: Vec::new(),
+            marks: Vec::new(),
+            free: Vec::new(),
+            grey: Vec::new(),
+            strings: HashMap::new(),
+            bytes_allocated: 0,
+            next_gc: INITIAL_GC_THRESHOLD,
+            stress: false,
+            log: false,
+            peak_bytes: 0,
+            collections: 0,
+            total_pause: Duration::ZERO,
+        }
+    }
+
+    pub fn set_stress(&mut self, stress: bool) {
+        self.stress = stress;
+    }
+
+    pub fn set_log(&mut self, log: bool) {
+        self.log = log;
+    }
+
+    pub fn stats(&self) -> GcStats {
+        let mut live_objects = ObjectCounts::default();
+        for obj in self.objects.iter().flatten() {
+            let count = match obj {
+                Obj::String(_) => &mut live_objects.strings,
+                Obj::Function(_) => &mut live_objects.functions,
+                Obj::Native(_) => &mut live_objects.natives,
+                Obj::Closure(_) => &mut live_objects.closures,
+                Obj::Upvalue(_) => &mut live_objects.upvalues,
+                Obj::Class(_) => &mut live_objects.classes,
+                Obj::Instance(_) => &mut live_objects.instances,
+                Obj::BoundMethod(_) => &mut live_objects.bound_methods,
+            };
+            *count += 1;
+        }
+
+        GcStats {
+            bytes_allocated: self.bytes_allocated,
+            live_objects,
+            collections: self.collections,
+            total_pause: self.total_pause,
+            peak_bytes: self.peak_bytes,
+        }
+    }
+
+    // Allocation never collects by itself, since the heap doesn't know the roots. Owners that
+    // do should check this before allocating.
+    pub fn should_collect(&self) -> bool {
+        self.stress || self.bytes_allocated > self.next_gc
+    }
+
+    pub fn alloc(&mut self, obj: Obj) -> ObjRef {
+        self.bytes_allocated += obj.size();
+        self.peak_bytes = self.peak_bytes.max(self.bytes_allocated);
+        match self.free.pop() {
+            Some(index) => {
+                self.objects[index as usize] = Some(obj);
+                ObjRef(index)
+            }
+            None => {
+                self.objects.push(Some(obj));
+                self.marks.push(false);
+                ObjRef(self.objects.len() as u32 - 1)
+            }
+        }
+    }
+
+    // Return the string object for some text, allocating it the first time it is seen
+    pub fn intern(&mut self, s: &str) -> ObjRef {
+        if let Some(&string) = self.strings.get(s) {
+            return string;
+        }
+
+        let string = self.alloc(Obj::String(s.into()));
+        self.strings.insert(s.into(), string);
+        string
+    }
+
+    pub fn get(&self, obj: ObjRef) -> &Obj {
+        self.objects[obj.0 as usize]
+            .as_ref()
+            .unwrap_or_else(|| unreachable!("{:?} was used after being freed", obj))
+    }
+
+    pub fn get_mut(&mut self, obj: ObjRef) -> &mut Obj {
+        self.objects[obj.0 as usize]
+            .as_mut()
+            .unwrap_or_else(|| unreachable!("{:?} was used after being freed", obj))
+    }
+
+    // The number of objects currently allocated
+    pub fn len(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn mark_value(&mut self, value: Value) {
+        if let Value::Obj(obj) = value {
+            self.mark_object(obj);
+        }
+    }
+
+    pub fn mark_object(&mut self, obj: ObjRef) {
+        mark(&mut self.marks, &mut self.grey, obj);
+    }
+
+    // Free every object that isn't reachable from the roots, which the caller marks first
+    pub fn collect(&mut self, mark_roots: impl FnOnce(&mut Heap)) {
+        let start = Instant::now();
+        let (bytes_before, objects_before) = (self.bytes_allocated, self.len());
+
+        mark_roots(self);
+
+        while let Some(obj) = self.grey.pop() {
+            self.blacken(obj);
+        }
+
+        let marks = &self.marks;
+        self.strings.retain(|_, string| marks[string.0 as usize]);
+
+        self.bytes_allocated = 0;
+        for (index, slot) in self.objects.iter_mut().enumerate() {
+            let Some(obj) = slot else {
+                continue;
+            };
+
+            if self.marks[index] {
+                self.marks[index] = false;
+                self.bytes_allocated += obj.size();
+            } else {
+                *slot = None;
+                self.free.push(index as u32);
+            }
+        }
+
+        self.next_gc = (self.bytes_allocated * GC_GROWTH_FACTOR).max(INITIAL_GC_THRESHOLD);
+
+        let pause = start.elapsed();
+        self.collections += 1;
+        self.total_pause += pause;
+        if self.log {
+            eprintln!(
+                "[gc {}] {} -> {} bytes, freed {} of {} objects in {:?}, next at {} bytes",
+                self.collections,
+                bytes_before,
+                self.bytes_allocated,
+                objects_before - self.len(),
+                objects_before,
+                pause,
+                self.next_gc
+            );
+        }
+    }
+
+    // Mark everything a grey object refers to, which leaves it black
+    fn blacken(&mut self, obj: ObjRef) {
+        let Heap {
+            objects,
+            marks,
+            grey,
+            ..
+        } = self;
+        let mut mark_value = |value: Value| {
+            if let Value::Obj(obj) = value {
+                mark(marks, grey, obj);
+            }
+        };
+
+        match objects[obj.0 as usize].as_ref() {
+            Some(Obj::String(_)) | Some(Obj::Native(_)) | None => {}
+            Some(Obj::Function(function)) => {
+                if let Some(name) = function.name {
+                    mark_value(Value::Obj(name));
+                }
+                for &constant in &function.chunk.constants {
+                    mark_value(constant);
+                }
+            }
+            Some(Obj::Closure(closure)) => {
+                mark_value(Value::Obj(closure.function));
+                for &upvalue in &closure.upvalues {
+                    mark_value(Value::Obj(upvalue));
+                }
+            }
+            Some(Obj::Upvalue(Upvalue::Closed(value))) => mark_value(*value),
+            // An open upvalue's value is on the stack, which is a root
+            Some(Obj::Upvalue(Upvalue::Open(_))) => {}
+            Some(Obj::Class(class)) => {
+                mark_value(Value::Obj(class.name));
+                for (&name, &method) in &class.methods {
+                    mark_value(Value::Obj(name));
+                    mark_value(Value::Obj(method));
+                }
+            }
+            Some(Obj::Instance(instance)) => {
+                mark_value(Value::Obj(instance.class));
+                for (&name, &value) in &instance.fields {
+                    mark_value(Value::Obj(name));
+                    mark_value(value);
+                }
+            }
+            Some(Obj::BoundMethod(bound)) => {
+                mark_value(bound.receiver);
+                mark_value(Value::Obj(bound.method));
+            }
+        }
+    }
+
+    // The accessors below are for objects whose kind is known from where the handle came from,
+    // such as a closure's function, so any other kind is a bug in the compiler or VM
+
+    pub fn string(&self, obj: ObjRef) -> &str {
+        match self.get(obj) {
+            Obj::String(s) => s,
+            other => unreachable!("Expected a string, found {:?}", other),
+        }
+    }
+
+    pub fn function(&self, obj: ObjRef) -> &Function {
+        match self.get(obj) {
+            Obj::Function(function) => function,
+            other => unreachable!("Expected a function, found {:?}", other),
+        }
+    }
+
+    pub fn closure(&self, obj: ObjRef) -> &Closure {
+        match self.get(obj) {
+            Obj::Closure(closure) => closure,
+            other => unreachable!("Expected a closure, found {:?}", other),
+        }
+    }
+
+    pub fn closure_mut(&mut self, obj: ObjRef) -> &mut Closure {
+        match self.get_mut(obj) {
+            Obj::Closure(closure) => closure,
+            other => unreachable!("Expected a closure, found {:?}", other),
+        }
+    }
+
+    pub fn upvalue_mut(&mut self, obj: ObjRef) -> &mut Upvalue {
+        match self.get_mut(obj) {
+            Obj::Upvalue(upvalue) => upvalue,
+            other => unreachable!("Expected an upvalue, found {:?}", other),
+        }
+    }
+
+    pub fn class(&self, obj: ObjRef) -> &Class {
+        match self.get(obj) {
+            Obj::Class(class) => class,
+            other => unreachable!("Expected a class, found {:?}", other),
+        }
+    }
+
+    pub fn class_mut(&mut self, obj: ObjRef) -> &mut Class {
+        match self.get_mut(obj) {
+            Obj::Class(class) => class,
+            other => unreachable!("Expected a class, found {:?}", other),
+        }
+    }
+
+    // Format a value the way the tree-walking interpreter does
+    pub fn format(&self, value: Value) -> String {
+        match value {
+            Value::Nil => "nil".to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Number(n) => n.to_string(),
+            Value::Obj(obj) => match self.get(obj) {
+                Obj::String(s) => s.to_string(),
+                Obj::Function(function) => match function.name {
+                    Some(name) => format!("<fn {}>", self.string(name)),
+                    None => "<script>".to_string(),
+                },
+                Obj::Native(_) => "<native fn>".to_string(),
+                Obj::Closure(closure) => self.format(Value::Obj(closure.function)),
+                Obj::Upvalue(_) => "upvalue".to_string(),
+                Obj::Class(class) => self.string(class.name).to_string(),
+                Obj::Instance(instance) => {
+                    format!("{} instance", self.string(self.class(instance.class).name))
+                }
+                Obj::BoundMethod(bound) => self.format(Value::Obj(bound.method)),
+            },
+        }
+    }
+}
+
+// Turn a white object grey, leaving objects that are already grey or black alone
+fn mark(marks: &mut [bool], grey: &mut Vec<ObjRef>, obj: ObjRef) {
+    let marked = &mut marks[obj.0 as usize];
+    if !*marked {
+        *marked = true;
+        grey.push(obj);
+    }
+}
+
+#[cfg(test)]
+mod test_heap {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let mut heap = Heap::new();
+        let a = heap.intern("lox");
+        let b = heap.intern("lox");
+        let c = heap.intern("rlox");
+
+        // Equal strings are the same object, so values compare by handle
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!("lox", heap.string(a));
+        assert_eq!(Value::Obj(a), Value::Obj(b));
+    }
+
+    #[test]
+    fn test_format() {
+        let mut heap = Heap::new();
+        let name = heap.intern("Point");
+        let class = heap.alloc(Obj::Class(Class {
+            name,
+            methods: HashMap::new(),
+        }));
+        let instance = heap.alloc(Obj::Instance(Instance {
+            class,
+            fields: HashMap::new(),
+        }));
+        let script = heap.alloc(Obj::Function(Function::default()));
+
+        assert_eq!("2.5", heap.format(Value::Number(2.5)));
+        assert_eq!("3", heap.format(Value::Number(3.0)));
+        assert_eq!("nil", heap.format(Value::Nil));
+        assert_eq!("Point", heap.format(Value::Obj(class)));
+        assert_eq!("Point instance", heap.format(Value::Obj(instance)));
+        assert_eq!("<script>", heap.format(Value::Obj(script)));
+    }
+
+    #[test]
+    fn test_collect() {
+        let mut heap = Heap::new();
+        let name = heap.intern("Point");
+        let class = heap.alloc(Obj::Class(Class {
+            name,
+            methods: HashMap::new(),
+        }));
+        let field = heap.intern("x");
+        let garbage = heap.intern("garbage");
+        let instance = heap.alloc(Obj::Instance(Instance {
+            class,
+            fields: HashMap::from([(field, Value::Obj(garbage))]),
+        }));
+        heap.alloc(Obj::Instance(Instance {
+            class,
+            fields: HashMap::new(),
+        }));
+        assert_eq!(6, heap.len());
+
+        // Everything the root refers to survives, directly or not
+        heap.collect(|heap| heap.mark_object(instance));
+        assert_eq!(5, heap.len());
+        assert_eq!("Point instance", heap.format(Value::Obj(instance)));
+
+        // Unreachable strings leave the intern table, and freed slots are reused
+        heap.collect(|heap| heap.mark_object(class));
+        assert_eq!(2, heap.len());
+        heap.intern("garbage");
+        assert_eq!(3, heap.len());
+        assert_eq!(6, heap.objects.len());
+    }
+
+    #[test]
+    fn test_collect_cycles() {
+        let mut heap = Heap::new();
+        let name = heap.intern("Node");
+        let class = heap.alloc(Obj::Class(Class {
+            name,
+            methods: HashMap::new(),
+        }));
+        let a = heap.alloc(Obj::Instance(Instance {
+            class,
+            fields: HashMap::new(),
+        }));
+        let next = heap.intern("next");
+        let b = heap.alloc(Obj::Instance(Instance {
+            class,
+            fields: HashMap::from([(next, Value::Obj(a))]),
+        }));
+        match heap.get_mut(a) {
+            Obj::Instance(instance) => instance.fields.insert(next, Value::Obj(b)),
+            _ => unreachable!(),
+        };
+
+        // Objects that only refer to each other are still garbage
+        heap.collect(|_| {});
+        assert!(heap.is_empty());
+    }
+
+    #[test]
+    fn test_threshold() {
+        let mut heap = Heap::new();
+        assert!(!heap.should_collect());
+        heap.set_stress(true);
+        assert!(heap.should_collect());
+        heap.set_stress(false);
+
+        heap.alloc(Obj::String("x".repeat(INITIAL_GC_THRESHOLD).into()));
+        assert!(heap.should_collect());
+        heap.collect(|_| {});
+        assert!(!heap.should_collect());
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut heap = Heap::new();
+        let name = heap.intern("Point");
+        let class = heap.alloc(Obj::Class(Class {
+            name,
+            methods: HashMap::new(),
+        }));
+        heap.alloc(Obj::Instance(Instance {
+            class,
+            fields: HashMap::new(),
+        }));
+        heap.intern("garbage");
+
+        let stats = heap.stats();
+        assert_eq!(2, stats.live_objects.strings);
+        assert_eq!(1, stats.live_objects.classes);
+        assert_eq!(1, stats.live_objects.instances);
+        assert_eq!(4, stats.live_objects.total());
+        assert_eq!(0, stats.collections);
+        assert_eq!(stats.bytes_allocated, stats.peak_bytes);
+
+        // Collecting counts, and the peak remembers what the heap held before
+        heap.collect(|heap| heap.mark_object(class));
+        let after = heap.stats();
+        assert_eq!(2, after.live_objects.total());
+        assert_eq!(1, after.collections);
+        assert!(after.bytes_allocated < after.peak_bytes);
+        assert_eq!(stats.peak_bytes, after.peak_bytes);
+    }
+}
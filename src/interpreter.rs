@@ -1,17 +1,1042 @@
-use crate::lexer::LineLexer;
+use crate::callable::{natives, LoxCallable, LoxFunction};
+use crate::class::{LoxClass, LoxInstance};
+use crate::compiler::BytecodeError;
+use crate::environment::Environment;
+use crate::lexer::{KwordType, LexError, Lexer, LitType, OpType, Span, Token, TokenType};
+use crate::parser::{Expr, ExprId, ParseError, Parser, Stmt, StmtVisitor, Visitor};
+use crate::resolver::{Locals, ResolveError, Resolver};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
 
-// Execute a line of lox
-pub fn exec_line(line: String) -> Result<(), String> {
-    let mut lexer = LineLexer::new(line);
+// How deep calls can nest before the program is assumed to recurse forever, matching the VM
+const MAX_CALL_DEPTH: usize = 1024;
 
-    let tokens = lexer.lex_tokens();
+// A runtime value produced by evaluating Lox code
+#[derive(Debug, Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Callable(Rc<dyn LoxCallable>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(l), Value::Bool(r)) => l == r,
+            (Value::Number(l), Value::Number(r)) => l == r,
+            (Value::String(l), Value::String(r)) => l == r,
+            // Callables are only equal to themselves
+            (Value::Callable(l), Value::Callable(r)) => Rc::ptr_eq(l, r),
+            (Value::Class(l), Value::Class(r)) => Rc::ptr_eq(l, r),
+            (Value::Instance(l), Value::Instance(r)) => Rc::ptr_eq(l, r),
+            _ => false,
+        }
+    }
+}
+
+impl Value {
+    // Lox treats nil and false as falsey and everything else as truthy
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Callable(c) => write!(f, "{}", c),
+            Value::Class(c) => write!(f, "{}", c),
+            Value::Instance(i) => write!(f, "{}", i.borrow()),
+        }
+    }
+}
+
+impl From<&LitType> for Value {
+    fn from(lit_type: &LitType) -> Self {
+        match lit_type {
+            LitType::Number(n) => Value::Number(*n),
+            LitType::String(s) => Value::String(s.clone()),
+            LitType::True => Value::Bool(true),
+            LitType::False => Value::Bool(false),
+            LitType::Nil => Value::Nil,
+            LitType::Identifier(_) => unreachable!("Identifiers are not literal values"),
+        }
+    }
+}
+
+// The kinds of error that can be raised while evaluating Lox code
+#[derive(Debug, PartialEq)]
+pub enum RuntimeErrorKind {
+    OperandMustBeNumber,
+    OperandsMustBeNumbers,
+    OperandsMustBeNumbersOrStrings,
+    UndefinedVariable(String),
+    NotCallable,
+    WrongArity { expected: usize, got: usize },
+    OnlyInstancesHaveProperties,
+    OnlyInstancesHaveFields,
+    UndefinedProperty(String),
+    SuperclassMustBeClass,
+    ThisOutsideClass,
+    SuperOutsideClass,
+    StackOverflow,
+    MalformedBytecode,
+}
+
+impl fmt::Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeErrorKind::OperandMustBeNumber => write!(f, "Operand must be a number."),
+            RuntimeErrorKind::OperandsMustBeNumbers => write!(f, "Operands must be numbers."),
+            RuntimeErrorKind::OperandsMustBeNumbersOrStrings => {
+                write!(f, "Operands must be two numbers or two strings.")
+            }
+            RuntimeErrorKind::UndefinedVariable(name) => write!(f, "Undefined variable '{}'.", name),
+            RuntimeErrorKind::NotCallable => write!(f, "Can only call functions and classes."),
+            RuntimeErrorKind::WrongArity { expected, got } => {
+                write!(f, "Expected {} arguments but got {}.", expected, got)
+            }
+            RuntimeErrorKind::OnlyInstancesHaveProperties => {
+                write!(f, "Only instances have properties.")
+            }
+            RuntimeErrorKind::OnlyInstancesHaveFields => write!(f, "Only instances have fields."),
+            RuntimeErrorKind::UndefinedProperty(name) => write!(f, "Undefined property '{}'.", name),
+            RuntimeErrorKind::SuperclassMustBeClass => write!(f, "Superclass must be a class."),
+            RuntimeErrorKind::ThisOutsideClass => write!(f, "Can't use 'this' outside of a class."),
+            RuntimeErrorKind::SuperOutsideClass => write!(f, "Can't use 'super' outside of a class."),
+            RuntimeErrorKind::StackOverflow => write!(f, "Stack overflow."),
+            RuntimeErrorKind::MalformedBytecode => {
+                write!(f, "Malformed bytecode: an instruction was given the wrong kind of value.")
+            }
+        }
+    }
+}
+
+impl RuntimeErrorKind {
+    // Attach the location of the token that caused the error
+    pub fn at(self, token: &Token) -> RuntimeError {
+        RuntimeError::new(self, token.span)
+    }
+}
+
+// An error raised while evaluating Lox code, along with where it was raised
+#[derive(Debug, PartialEq)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub span: Span,
+}
+
+impl RuntimeError {
+    pub fn new(kind: RuntimeErrorKind, span: Span) -> Self {
+        RuntimeError { kind, span }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+// The ways statement execution can stop early: returning from a function or failing
+#[derive(Debug, PartialEq)]
+pub enum Unwind {
+    Return(Value),
+    Error(RuntimeError),
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(err: RuntimeError) -> Self {
+        Unwind::Error(err)
+    }
+}
+
+// An error found before the program runs
+#[derive(Debug)]
+pub enum CompileError {
+    Lex(LexError),
+    Parse(ParseError),
+    Resolve(ResolveError),
+    Bytecode(BytecodeError),
+}
+
+// Reported in the reference implementation's format: [line N] Error at 'x': message
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::Lex(err) => write!(f, "[line {}] Error: {}", err.span.line, err),
+            CompileError::Parse(err) => write!(
+                f,
+                "[line {}] Error{}: {}",
+                err.token.span.line,
+                location(&err.token),
+                err
+            ),
+            CompileError::Resolve(err) => write!(
+                f,
+                "[line {}] Error{}: {}",
+                err.token.span.line,
+                location(&err.token),
+                err
+            ),
+            CompileError::Bytecode(err) => write!(f, "[line {}] Error: {}", err.span.line, err),
+        }
+    }
+}
+
+// Describe where in the source a token is, for error messages
+fn location(token: &Token) -> String {
+    match token.token_type {
+        TokenType::Eof => " at end".to_string(),
+        _ => format!(" at '{}'", token.lexeme),
+    }
+}
+
+// Why running a piece of Lox source failed
+#[derive(Debug)]
+pub enum LoxError {
+    // Every error found before running; nothing was executed
+    Compile(Vec<CompileError>),
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoxError::Compile(errs) => {
+                let errs = errs.iter().map(|e| e.to_string()).collect::<Vec<String>>();
+                write!(f, "{}", errs.join("\n"))
+            }
+            LoxError::Runtime(err) => write!(f, "{}\n[line {}]", err, err.span.line),
+        }
+    }
+}
+
+// Lex source code, separating the tokens from the errors
+pub fn lex(src: &str) -> (Vec<Token>, Vec<CompileError>) {
+    let mut errors = Vec::new();
+    let mut tokens = Vec::new();
+    for result in Lexer::new(src).lex_tokens() {
+        match result {
+            Ok(token) => tokens.push(token),
+            Err(err) => errors.push(CompileError::Lex(err)),
+        }
+    }
+
+    (tokens, errors)
+}
+
+// Lex and parse source code, collecting every error found along the way
+pub fn parse(src: &str) -> Result<Vec<Stmt>, Vec<CompileError>> {
+    let (tokens, mut errors) = lex(src);
+
+    // Parse whatever lexed cleanly so that syntax errors are reported too
+    match Parser::new(tokens).parse_tokens() {
+        Ok(stmts) if errors.is_empty() => Ok(stmts),
+        Ok(_) => Err(errors),
+        Err(errs) => {
+            errors.extend(errs.into_iter().map(CompileError::Parse));
+            Err(errors)
+        }
+    }
+}
+
+// Lex, parse and resolve source code, collecting every error found along the way
+pub fn compile(src: &str) -> Result<(Vec<Stmt>, Locals), Vec<CompileError>> {
+    let stmts = parse(src)?;
+
+    match Resolver::new().resolve(&stmts) {
+        Ok(locals) => Ok((stmts, locals)),
+        Err(errs) => Err(errs.into_iter().map(CompileError::Resolve).collect()),
+    }
+}
+
+// Lex, parse and resolve a lone expression
+pub fn compile_expression(src: &str) -> Result<(Expr, Locals), Vec<CompileError>> {
+    let (tokens, mut errors) = lex(src);
+
+    let expr = match Parser::new(tokens).parse_expression() {
+        Ok(expr) => expr,
+        Err(errs) => {
+            errors.extend(errs.into_iter().map(CompileError::Parse));
+            return Err(errors);
+        }
+    };
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    match Resolver::new().resolve_expr(&expr) {
+        Ok(locals) => Ok((expr, locals)),
+        Err(errs) => Err(errs.into_iter().map(CompileError::Resolve).collect()),
+    }
+}
+
+// A tree-walking evaluator for the parsed AST
+pub struct Interpreter {
+    globals: Rc<RefCell<Environment>>,
+    // The innermost scope of the code currently executing
+    environment: Rc<RefCell<Environment>>,
+    // Scope distances of local variable references, as found by the resolver
+    locals: Locals,
+    // How many calls are running, so that runaway recursion is an error rather than a crash
+    call_depth: usize,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        let mut globals = Environment::new();
+        for native in natives() {
+            globals.define(native.name.to_string(), Value::Callable(Rc::new(native)));
+        }
+
+        let globals = Rc::new(RefCell::new(globals));
+        Interpreter {
+            globals: Rc::clone(&globals),
+            environment: globals,
+            locals: HashMap::new(),
+            call_depth: 0,
+        }
+    }
+
+    // Every global variable, function and class, natives included, sorted by name
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        let mut globals: Vec<(String, Value)> = self
+            .globals
+            .borrow()
+            .bindings()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        globals.sort_by(|(l, _), (r, _)| l.cmp(r));
+        globals
+    }
+
+    // Record the scope distances found by resolving code this interpreter will run
+    pub fn resolve(&mut self, locals: Locals) {
+        self.locals.extend(locals);
+    }
+
+    // Execute a list of statements, stopping at the first runtime error
+    pub fn interpret(&mut self, stmts: &[Stmt]) -> Result<(), RuntimeError> {
+        match self.execute(stmts) {
+            // A top-level 'return' just ends the program
+            Ok(()) | Err(Unwind::Return(_)) => Ok(()),
+            Err(Unwind::Error(err)) => Err(err),
+        }
+    }
+
+    // Compile and execute source code, keeping anything it defines for later runs
+    pub fn run(&mut self, src: &str) -> Result<(), LoxError> {
+        let (stmts, locals) = compile(src).map_err(LoxError::Compile)?;
+        self.resolve(locals);
+        self.interpret(&stmts).map_err(LoxError::Runtime)
+    }
+
+    // Compile and evaluate a lone expression, returning its value
+    pub fn run_expression(&mut self, src: &str) -> Result<Value, LoxError> {
+        let (expr, locals) = compile_expression(src).map_err(LoxError::Compile)?;
+        self.resolve(locals);
+        self.evaluate(&expr).map_err(LoxError::Runtime)
+    }
+
+    fn execute(&mut self, stmts: &[Stmt]) -> Result<(), Unwind> {
+        for stmt in stmts {
+            self.visit_stmt(stmt)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn evaluate(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        self.visit_expr(expr)
+    }
+
+    // Execute statements inside the given scope, restoring the current scope afterwards
+    pub(crate) fn execute_block(
+        &mut self,
+        stmts: &[Stmt],
+        environment: Environment,
+    ) -> Result<(), Unwind> {
+        let previous = std::mem::replace(&mut self.environment, Rc::new(RefCell::new(environment)));
+        let result = self.execute(stmts);
+        self.environment = previous;
+
+        result
+    }
 
-    dbg!(tokens);
+    // Read a variable from the scope the resolver bound it to, or from the globals
+    fn look_up_variable(&self, name: &Token, id: ExprId) -> Result<Value, RuntimeError> {
+        let var_name = name.str_val().expect("Expected identifier");
+        match self.locals.get(&id) {
+            Some(&distance) => Environment::get_at(&self.environment, distance, &var_name),
+            None => self.globals.borrow().get(&var_name),
+        }
+        .map_err(|kind| kind.at(name))
+    }
 
-    Ok(())
+    fn eval_binary(
+        &mut self,
+        left: Value,
+        op: &Token,
+        right: Value,
+    ) -> Result<Value, RuntimeError> {
+        let op_type = *op.op_type().expect("Expected operator");
+        match (op_type, left, right) {
+            (OpType::EqualEqual, l, r) => Ok(Value::Bool(l == r)),
+            (OpType::BangEqual, l, r) => Ok(Value::Bool(l != r)),
+            (OpType::Plus, Value::Number(l), Value::Number(r)) => Ok(Value::Number(l + r)),
+            (OpType::Plus, Value::String(l), Value::String(r)) => Ok(Value::String(l + &r)),
+            (OpType::Plus, _, _) => Err(RuntimeErrorKind::OperandsMustBeNumbersOrStrings.at(op)),
+            (op_type, Value::Number(l), Value::Number(r)) => Ok(match op_type {
+                OpType::Minus => Value::Number(l - r),
+                OpType::Star => Value::Number(l * r),
+                OpType::Slash => Value::Number(l / r),
+                OpType::Greater => Value::Bool(l > r),
+                OpType::GreaterEqual => Value::Bool(l >= r),
+                OpType::Less => Value::Bool(l < r),
+                OpType::LessEqual => Value::Bool(l <= r),
+                _ => unreachable!("{} is not a binary operator", op_type),
+            }),
+            _ => Err(RuntimeErrorKind::OperandsMustBeNumbers.at(op)),
+        }
+    }
 }
 
-// Report to the user that an error has occurred
-pub fn report_err(line_no: u32, err_msg: &str) {
-    println!("[line {}] Error: {}", line_no, err_msg);
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Visitor<Result<Value, RuntimeError>> for Interpreter {
+    fn visit_expr(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        match *expr {
+            Expr::Assign(ref name, ref value, id) => {
+                let value = self.visit_expr(value)?;
+                let var_name = name.str_val().expect("Expected identifier");
+
+                match self.locals.get(&id) {
+                    Some(&distance) => Environment::assign_at(
+                        &self.environment,
+                        distance,
+                        &var_name,
+                        value.clone(),
+                    ),
+                    None => self.globals.borrow_mut().assign(&var_name, value.clone()),
+                }
+                .map_err(|kind| kind.at(name))?;
+
+                Ok(value)
+            }
+            Expr::Bin(ref left, ref op, ref right) => {
+                let left = self.visit_expr(left)?;
+                let right = self.visit_expr(right)?;
+                self.eval_binary(left, op, right)
+            }
+            Expr::Call(ref callee, ref paren, ref args) => {
+                let callee = self.visit_expr(callee)?;
+                let args = args
+                    .iter()
+                    .map(|arg| self.visit_expr(arg))
+                    .collect::<Result<Vec<Value>, RuntimeError>>()?;
+
+                let callable: Rc<dyn LoxCallable> = match callee {
+                    Value::Callable(callable) => callable,
+                    Value::Class(class) => class,
+                    _ => return Err(RuntimeErrorKind::NotCallable.at(paren)),
+                };
+
+                if args.len() != callable.arity() {
+                    return Err(RuntimeErrorKind::WrongArity {
+                        expected: callable.arity(),
+                        got: args.len(),
+                    }
+                    .at(paren));
+                }
+
+                if self.call_depth == MAX_CALL_DEPTH {
+                    return Err(RuntimeErrorKind::StackOverflow.at(paren));
+                }
+                self.call_depth += 1;
+                let result = callable.call(self, args);
+                self.call_depth -= 1;
+                result
+            }
+            Expr::Get(ref object, ref name) => match self.visit_expr(object)? {
+                Value::Instance(instance) => {
+                    LoxInstance::get(&instance, &name.str_val().expect("Expected identifier"))
+                        .map_err(|kind| kind.at(name))
+                }
+                _ => Err(RuntimeErrorKind::OnlyInstancesHaveProperties.at(name)),
+            },
+            Expr::Grouping(ref expr) => self.visit_expr(expr),
+            Expr::Lit(ref lit_type, _) => Ok(Value::from(lit_type)),
+            Expr::Logical(ref left, kword, ref right) => {
+                let left = self.visit_expr(left)?;
+
+                // Short-circuit, yielding whichever operand decided the result
+                match (kword, left.is_truthy()) {
+                    (KwordType::Or, true) | (KwordType::And, false) => Ok(left),
+                    _ => self.visit_expr(right),
+                }
+            }
+            Expr::Unary(ref op, ref expr) => {
+                let value = self.visit_expr(expr)?;
+                match (op.op_type(), value) {
+                    (Some(OpType::Bang), value) => Ok(Value::Bool(!value.is_truthy())),
+                    (Some(OpType::Minus), Value::Number(n)) => Ok(Value::Number(-n)),
+                    (Some(OpType::Minus), _) => Err(RuntimeErrorKind::OperandMustBeNumber.at(op)),
+                    _ => unreachable!("{} is not a unary operator", op.lexeme),
+                }
+            }
+            Expr::Set(ref object, ref name, ref value) => {
+                let Value::Instance(instance) = self.visit_expr(object)? else {
+                    return Err(RuntimeErrorKind::OnlyInstancesHaveFields.at(name));
+                };
+
+                let value = self.visit_expr(value)?;
+                instance
+                    .borrow_mut()
+                    .set(name.str_val().expect("Expected identifier"), value.clone());
+
+                Ok(value)
+            }
+            Expr::Super(ref keyword, ref method, id) => {
+                let Some(&distance) = self.locals.get(&id) else {
+                    return Err(RuntimeErrorKind::SuperOutsideClass.at(keyword));
+                };
+
+                // 'this' is always bound in the scope just inside the one binding 'super'
+                let Ok(Value::Class(superclass)) =
+                    Environment::get_at(&self.environment, distance, "super")
+                else {
+                    return Err(RuntimeErrorKind::SuperOutsideClass.at(keyword));
+                };
+                let Ok(Value::Instance(instance)) =
+                    Environment::get_at(&self.environment, distance - 1, "this")
+                else {
+                    return Err(RuntimeErrorKind::ThisOutsideClass.at(keyword));
+                };
+
+                let name = method.str_val().expect("Expected identifier");
+                match superclass.find_method(&name) {
+                    Some(found) => Ok(Value::Callable(Rc::new(found.bind(instance)))),
+                    None => Err(RuntimeErrorKind::UndefinedProperty(name).at(method)),
+                }
+            }
+            Expr::This(ref keyword, id) => match self.locals.get(&id) {
+                Some(&distance) => Environment::get_at(&self.environment, distance, "this")
+                    .map_err(|_| RuntimeErrorKind::ThisOutsideClass.at(keyword)),
+                None => Err(RuntimeErrorKind::ThisOutsideClass.at(keyword)),
+            },
+            Expr::Variable(ref name, id) => self.look_up_variable(name, id),
+        }
+    }
+}
+
+impl StmtVisitor<Result<(), Unwind>> for Interpreter {
+    fn visit_stmt(&mut self, stmt: &Stmt) -> Result<(), Unwind> {
+        match *stmt {
+            Stmt::Block(ref stmts) => {
+                let environment = Environment::with_enclosing(Rc::clone(&self.environment));
+                self.execute_block(stmts, environment)
+            }
+            Stmt::Class(ref decl) => {
+                let superclass = match decl.superclass {
+                    Some(ref expr @ Expr::Variable(ref superclass_name, _)) => {
+                        match self.visit_expr(expr)? {
+                            Value::Class(superclass) => Some(superclass),
+                            _ => {
+                                return Err(RuntimeErrorKind::SuperclassMustBeClass
+                                    .at(superclass_name)
+                                    .into())
+                            }
+                        }
+                    }
+                    Some(_) => unreachable!("Superclass must be a variable expression"),
+                    None => None,
+                };
+
+                let name = decl.name.str_val().expect("Expected identifier");
+                self.environment
+                    .borrow_mut()
+                    .define(name.clone(), Value::Nil);
+
+                // Methods of a subclass close over a scope that binds 'super'
+                let mut method_env = Rc::clone(&self.environment);
+                if let Some(ref superclass) = superclass {
+                    let mut environment = Environment::with_enclosing(method_env);
+                    environment.define("super".to_string(), Value::Class(Rc::clone(superclass)));
+                    method_env = Rc::new(RefCell::new(environment));
+                }
+
+                let methods = decl
+                    .methods
+                    .iter()
+                    .map(|method| {
+                        let method_name = method.name.str_val().expect("Expected identifier");
+                        let is_initializer = method_name == "init";
+                        let function = LoxFunction::new(
+                            Rc::clone(method),
+                            Rc::clone(&method_env),
+                            is_initializer,
+                        );
+
+                        (method_name, Rc::new(function))
+                    })
+                    .collect::<HashMap<_, _>>();
+
+                let class = LoxClass::new(name.clone(), superclass, methods);
+                self.environment
+                    .borrow_mut()
+                    .assign(&name, Value::Class(Rc::new(class)))
+                    .map_err(|kind| kind.at(&decl.name))?;
+                Ok(())
+            }
+            Stmt::Expression(ref expr) => {
+                self.visit_expr(expr)?;
+                Ok(())
+            }
+            Stmt::Function(ref decl) => {
+                let function =
+                    LoxFunction::new(Rc::clone(decl), Rc::clone(&self.environment), false);
+                self.environment.borrow_mut().define(
+                    decl.name.str_val().expect("Expected identifier"),
+                    Value::Callable(Rc::new(function)),
+                );
+                Ok(())
+            }
+            Stmt::If(ref condition, ref then_branch, ref else_branch) => {
+                if self.visit_expr(condition)?.is_truthy() {
+                    self.visit_stmt(then_branch)
+                } else if let Some(else_branch) = else_branch {
+                    self.visit_stmt(else_branch)
+                } else {
+                    Ok(())
+                }
+            }
+            Stmt::Print(ref expr) => {
+                println!("{}", self.visit_expr(expr)?);
+                Ok(())
+            }
+            Stmt::Return(_, ref value) => {
+                let value = match value {
+                    Some(expr) => self.visit_expr(expr)?,
+                    None => Value::Nil,
+                };
+
+                Err(Unwind::Return(value))
+            }
+            Stmt::Var(ref name, ref initializer) => {
+                let value = match initializer {
+                    Some(expr) => self.visit_expr(expr)?,
+                    None => Value::Nil,
+                };
+
+                self.environment
+                    .borrow_mut()
+                    .define(name.str_val().expect("Expected identifier"), value);
+                Ok(())
+            }
+            Stmt::While(ref condition, ref body) => {
+                while self.visit_expr(condition)?.is_truthy() {
+                    self.visit_stmt(body)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_interpreter {
+    use super::*;
+
+    fn parse_str(src: &str) -> Vec<Stmt> {
+        let tokens = Lexer::new(src)
+            .lex_tokens()
+            .into_iter()
+            .map(|tok| tok.unwrap())
+            .collect();
+
+        Parser::new(tokens).parse_tokens().unwrap()
+    }
+
+    // Resolve and execute statements with the given interpreter
+    fn run(interpreter: &mut Interpreter, stmts: &[Stmt]) -> Result<(), RuntimeErrorKind> {
+        interpreter.resolve(Resolver::new().resolve(stmts).unwrap());
+        interpreter.interpret(stmts).map_err(|e| e.kind)
+    }
+
+    // Evaluate the expression in a single expression statement
+    fn eval_in(interpreter: &mut Interpreter, src: &str) -> Result<Value, RuntimeErrorKind> {
+        let stmts = parse_str(&format!("{};", src));
+        interpreter.resolve(Resolver::new().resolve(&stmts).unwrap());
+
+        match stmts.first() {
+            Some(Stmt::Expression(expr)) => interpreter.evaluate(expr).map_err(|e| e.kind),
+            _ => panic!("Expected an expression statement"),
+        }
+    }
+
+    fn eval_str(src: &str) -> Result<Value, RuntimeErrorKind> {
+        eval_in(&mut Interpreter::new(), src)
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(Ok(Value::Number(7.0)), eval_str("1 + 2 * 3"));
+        assert_eq!(Ok(Value::Number(9.0)), eval_str("(1 + 2) * 3"));
+        assert_eq!(Ok(Value::Number(-2.5)), eval_str("-5 / 2"));
+        assert_eq!(
+            Ok(Value::String("cat dog".to_string())),
+            eval_str("\"cat \" + \"dog\"")
+        );
+    }
+
+    #[test]
+    fn test_truthiness_and_equality() {
+        assert_eq!(Ok(Value::Bool(true)), eval_str("!nil"));
+        assert_eq!(Ok(Value::Bool(false)), eval_str("!0"));
+        assert_eq!(Ok(Value::Bool(true)), eval_str("nil == nil"));
+        assert_eq!(Ok(Value::Bool(false)), eval_str("314 == \"pi\""));
+        assert_eq!(Ok(Value::Bool(false)), eval_str("123 == \"123\""));
+        assert_eq!(Ok(Value::Bool(true)), eval_str("\"cat\" != \"dog\""));
+        assert_eq!(Ok(Value::Bool(true)), eval_str("1 <= 1"));
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        assert_eq!(Err(RuntimeErrorKind::OperandMustBeNumber), eval_str("-\"a\""));
+        assert_eq!(
+            Err(RuntimeErrorKind::OperandsMustBeNumbers),
+            eval_str("1 < true")
+        );
+        assert_eq!(
+            Err(RuntimeErrorKind::OperandsMustBeNumbersOrStrings),
+            eval_str("1 + \"a\"")
+        );
+        assert_eq!(
+            Err(RuntimeErrorKind::UndefinedVariable("x".to_string())),
+            eval_str("x")
+        );
+    }
+
+    #[test]
+    fn test_error_spans() {
+        let mut interpreter = Interpreter::new();
+        let stmts = parse_str("var a = 1;\nprint a +\n  nil;");
+        interpreter.resolve(Resolver::new().resolve(&stmts).unwrap());
+
+        // The error points at the operator that failed
+        let err = interpreter.interpret(&stmts).unwrap_err();
+        assert_eq!(RuntimeErrorKind::OperandsMustBeNumbersOrStrings, err.kind);
+        assert_eq!((2, 9), (err.span.line, err.span.column));
+
+        let stmts = parse_str("fun f() {}\n\nf(1);");
+        interpreter.resolve(Resolver::new().resolve(&stmts).unwrap());
+        assert_eq!(3, interpreter.interpret(&stmts).unwrap_err().span.line);
+    }
+
+    #[test]
+    fn test_run_expression() {
+        let mut interpreter = Interpreter::new();
+        interpreter.run("var a = 1; fun f(x) { return x + a; }").unwrap();
+        assert_eq!(Value::Number(3.0), interpreter.run_expression("f(2)").unwrap());
+
+        // Runtime errors leave earlier definitions in place
+        assert!(matches!(
+            interpreter.run_expression("f(nil)"),
+            Err(LoxError::Runtime(_))
+        ));
+        assert_eq!(Value::Number(2.0), interpreter.run_expression("a = 2").unwrap());
+        assert_eq!(Value::Number(4.0), interpreter.run_expression("f(a)").unwrap());
+
+        // Statements and trailing tokens aren't expressions
+        assert!(matches!(
+            interpreter.run_expression("print a"),
+            Err(LoxError::Compile(_))
+        ));
+        assert!(matches!(
+            interpreter.run_expression("a a"),
+            Err(LoxError::Compile(_))
+        ));
+    }
+
+    #[test]
+    fn test_logical() {
+        // The deciding operand is returned rather than a coerced bool
+        assert_eq!(Ok(Value::Bool(false)), eval_str("true and false"));
+        assert_eq!(Ok(Value::Number(2.0)), eval_str("1 and 2"));
+        assert_eq!(Ok(Value::Nil), eval_str("nil and x"));
+        assert_eq!(
+            Ok(Value::String("hi".to_string())),
+            eval_str("nil or \"hi\"")
+        );
+        assert_eq!(Ok(Value::Number(1.0)), eval_str("1 or x"));
+    }
+
+    #[test]
+    fn test_scopes() {
+        let mut interpreter = Interpreter::new();
+        let stmts = parse_str(
+            "var a = \"global\"; var b = 1; \
+             { var a = \"inner\"; b = a; { a = \"innermost\"; } var c = a; b = c; }",
+        );
+        run(&mut interpreter, &stmts).unwrap();
+
+        // The block's 'a' shadowed the global one, while assignments to 'b' walked outward
+        assert_eq!(
+            Ok(Value::String("global".to_string())),
+            eval_in(&mut interpreter, "a")
+        );
+        assert_eq!(
+            Ok(Value::String("innermost".to_string())),
+            eval_in(&mut interpreter, "b")
+        );
+        // Block-local variables are gone once the block ends
+        assert_eq!(
+            Err(RuntimeErrorKind::UndefinedVariable("c".to_string())),
+            eval_in(&mut interpreter, "c")
+        );
+    }
+
+    #[test]
+    fn test_scope_restored_after_error() {
+        let mut interpreter = Interpreter::new();
+        let stmts = parse_str("var a = 1; { var a = 2; a = -\"oops\"; }");
+
+        assert_eq!(
+            Err(RuntimeErrorKind::OperandMustBeNumber),
+            run(&mut interpreter, &stmts)
+        );
+        assert_eq!(Ok(Value::Number(1.0)), eval_in(&mut interpreter, "a"));
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let mut interpreter = Interpreter::new();
+        let stmts = parse_str(
+            "var sum = 0; for (var i = 1; i <= 10; i = i + 1) { sum = sum + i; } \
+             var n = 0; while (n < 3) n = n + 1; \
+             var branch; if (nil) branch = \"then\"; else if (0) branch = \"else if\"; else branch = \"else\";",
+        );
+        run(&mut interpreter, &stmts).unwrap();
+
+        assert_eq!(Ok(Value::Number(55.0)), eval_in(&mut interpreter, "sum"));
+        assert_eq!(Ok(Value::Number(3.0)), eval_in(&mut interpreter, "n"));
+        assert_eq!(
+            Ok(Value::String("else if".to_string())),
+            eval_in(&mut interpreter, "branch")
+        );
+        // The loop variable is scoped to the desugared for loop
+        assert_eq!(
+            Err(RuntimeErrorKind::UndefinedVariable("i".to_string())),
+            eval_in(&mut interpreter, "i")
+        );
+    }
+
+    #[test]
+    fn test_short_circuit_side_effects() {
+        let mut interpreter = Interpreter::new();
+        let stmts = parse_str("var a = 0; false and (a = 1); true or (a = 2); nil or (a = 3);");
+        run(&mut interpreter, &stmts).unwrap();
+
+        // Only the right operand of the last expression was evaluated
+        assert_eq!(Ok(Value::Number(3.0)), eval_in(&mut interpreter, "a"));
+    }
+
+    #[test]
+    fn test_functions() {
+        let mut interpreter = Interpreter::new();
+        let stmts = parse_str(
+            "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } \
+             var f = fib(10); \
+             fun noReturn() { 1; } var n = noReturn(); \
+             fun early() { while (true) { return \"early\"; } return \"late\"; } var e = early();",
+        );
+        run(&mut interpreter, &stmts).unwrap();
+
+        assert_eq!(Ok(Value::Number(55.0)), eval_in(&mut interpreter, "f"));
+        assert_eq!(Ok(Value::Nil), eval_in(&mut interpreter, "n"));
+        assert_eq!(
+            Ok(Value::String("early".to_string())),
+            eval_in(&mut interpreter, "e")
+        );
+        assert_eq!(
+            "<fn fib>",
+            eval_in(&mut interpreter, "fib").unwrap().to_string()
+        );
+        assert_eq!(
+            "<native fn>",
+            eval_in(&mut interpreter, "clock").unwrap().to_string()
+        );
+        assert!(matches!(
+            eval_in(&mut interpreter, "clock()"),
+            Ok(Value::Number(_))
+        ));
+
+        // There is no collector, but gcStats() has the same fields as on the VM
+        assert_eq!(
+            Ok(Value::Number(0.0)),
+            eval_in(&mut interpreter, "gcStats().liveObjects.instances")
+        );
+        assert_eq!(
+            Ok(Value::Number(0.0)),
+            eval_in(&mut interpreter, "gcStats().totalPauseMs")
+        );
+    }
+
+    #[test]
+    fn test_closures_bind_at_declaration() {
+        let mut interpreter = Interpreter::new();
+        let stmts = parse_str(
+            "var a = \"global\"; var first; var second; \
+             { fun show() { return a; } first = show(); var a = \"block\"; second = show(); }",
+        );
+        run(&mut interpreter, &stmts).unwrap();
+
+        // The closure keeps seeing the variable that was in scope when it was declared
+        assert_eq!(
+            Ok(Value::String("global".to_string())),
+            eval_in(&mut interpreter, "second")
+        );
+        assert_eq!(
+            eval_in(&mut interpreter, "first"),
+            eval_in(&mut interpreter, "second")
+        );
+    }
+
+    #[test]
+    fn test_closures() {
+        let mut interpreter = Interpreter::new();
+        let stmts = parse_str(
+            "fun makeCounter() { var i = 0; fun count() { i = i + 1; return i; } return count; } \
+             var a = makeCounter(); var b = makeCounter(); a(); a(); b(); var last = a();",
+        );
+        run(&mut interpreter, &stmts).unwrap();
+
+        // Each counter closes over its own 'i'
+        assert_eq!(Ok(Value::Number(3.0)), eval_in(&mut interpreter, "last"));
+        assert_eq!(Ok(Value::Number(2.0)), eval_in(&mut interpreter, "b()"));
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let mut interpreter = Interpreter::new();
+        run(&mut interpreter, &parse_str("fun f(a, b) {}")).unwrap();
+
+        assert_eq!(
+            Err(RuntimeErrorKind::WrongArity {
+                expected: 2,
+                got: 1
+            }),
+            eval_in(&mut interpreter, "f(1)")
+        );
+        assert_eq!(
+            Err(RuntimeErrorKind::NotCallable),
+            eval_in(&mut interpreter, "\"f\"()")
+        );
+    }
+
+    #[test]
+    fn test_classes() {
+        let mut interpreter = Interpreter::new();
+        let stmts = parse_str(
+            "class Breakfast { \
+               init(meat, bread) { this.meat = meat; this.bread = bread; } \
+               serve(who) { return \"Enjoy your \" + this.meat + \" and \" + this.bread + \", \" + who + \".\"; } \
+             } \
+             var breakfast = Breakfast(\"sausage\", \"sourdough\"); \
+             var served = breakfast.serve(\"Dear Reader\"); \
+             var method = breakfast.serve; breakfast.bread = \"toast\"; var bound = method(\"you\");",
+        );
+        run(&mut interpreter, &stmts).unwrap();
+
+        assert_eq!(
+            Ok(Value::String(
+                "Enjoy your sausage and sourdough, Dear Reader.".to_string()
+            )),
+            eval_in(&mut interpreter, "served")
+        );
+        // A method stays bound to the instance it was accessed from
+        assert_eq!(
+            Ok(Value::String(
+                "Enjoy your sausage and toast, you.".to_string()
+            )),
+            eval_in(&mut interpreter, "bound")
+        );
+        assert_eq!(
+            "Breakfast instance",
+            eval_in(&mut interpreter, "breakfast").unwrap().to_string()
+        );
+        assert_eq!(
+            "Breakfast",
+            eval_in(&mut interpreter, "Breakfast").unwrap().to_string()
+        );
+        // Calling init directly returns the instance again
+        assert_eq!(
+            eval_in(&mut interpreter, "breakfast"),
+            eval_in(&mut interpreter, "breakfast.init(\"ham\", \"rye\")")
+        );
+    }
+
+    #[test]
+    fn test_inheritance() {
+        let mut interpreter = Interpreter::new();
+        let stmts = parse_str(
+            "class A { name() { return \"A\"; } greet() { return \"I am \" + this.name(); } } \
+             class B < A { name() { return \"B\"; } parent() { return super.name(); } } \
+             class C < B { parent() { return super.parent() + \" via C\"; } } \
+             var c = C();",
+        );
+        run(&mut interpreter, &stmts).unwrap();
+
+        assert_eq!(
+            Ok(Value::String("I am B".to_string())),
+            eval_in(&mut interpreter, "c.greet()")
+        );
+        assert_eq!(
+            Ok(Value::String("A via C".to_string())),
+            eval_in(&mut interpreter, "c.parent()")
+        );
+    }
+
+    #[test]
+    fn test_class_errors() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .interpret(&parse_str("class A {} var a = A(); var notClass = 1;"))
+            .unwrap();
+
+        assert_eq!(
+            Err(RuntimeErrorKind::UndefinedProperty("missing".to_string())),
+            eval_in(&mut interpreter, "a.missing")
+        );
+        assert_eq!(
+            Err(RuntimeErrorKind::OnlyInstancesHaveProperties),
+            eval_in(&mut interpreter, "notClass.field")
+        );
+        assert_eq!(
+            Err(RuntimeErrorKind::OnlyInstancesHaveFields),
+            eval_in(&mut interpreter, "A.field = 1")
+        );
+        assert_eq!(
+            Err(RuntimeErrorKind::SuperclassMustBeClass),
+            run(&mut interpreter, &parse_str("class B < notClass {}"))
+        );
+    }
 }
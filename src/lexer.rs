@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum OpType {
     LeftParen,
@@ -22,38 +24,40 @@ pub enum OpType {
     LessEqual,
 }
 
-impl ToString for OpType {
-    fn to_string(&self) -> String {
-        match self {
-            OpType::LeftParen => '('.to_string(),
-            OpType::RightParen => ')'.to_string(),
-            OpType::LeftBrace => '{'.to_string(),
-            OpType::RightBrace => '}'.to_string(),
-            OpType::Comma => ','.to_string(),
-            OpType::Dot => '.'.to_string(),
-            OpType::Minus => '-'.to_string(),
-            OpType::Plus => '+'.to_string(),
-            OpType::Semicolon => ';'.to_string(),
-            OpType::Slash => '/'.to_string(),
-            OpType::SlashSlash => "//".to_string(),
-            OpType::Star => '*'.to_string(),
-            OpType::Bang => '!'.to_string(),
-            OpType::BangEqual => "!=".to_string(),
-            OpType::Equal => '='.to_string(),
-            OpType::EqualEqual => "==".to_string(),
-            OpType::Greater => '>'.to_string(),
-            OpType::GreaterEqual => ">=".to_string(),
-            OpType::Less => '<'.to_string(),
-            OpType::LessEqual => "<=".to_string(),
-        }
+impl fmt::Display for OpType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            OpType::LeftParen => "(",
+            OpType::RightParen => ")",
+            OpType::LeftBrace => "{",
+            OpType::RightBrace => "}",
+            OpType::Comma => ",",
+            OpType::Dot => ".",
+            OpType::Minus => "-",
+            OpType::Plus => "+",
+            OpType::Semicolon => ";",
+            OpType::Slash => "/",
+            OpType::SlashSlash => "//",
+            OpType::Star => "*",
+            OpType::Bang => "!",
+            OpType::BangEqual => "!=",
+            OpType::Equal => "=",
+            OpType::EqualEqual => "==",
+            OpType::Greater => ">",
+            OpType::GreaterEqual => ">=",
+            OpType::Less => "<",
+            OpType::LessEqual => "<=",
+        };
+
+        write!(f, "{}", op)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum LitType {
     Identifier(String),
     String(String),
-    Number(f32),
+    Number(f64),
     False,
     True,
     Nil,
@@ -71,20 +75,20 @@ impl PartialEq for LitType {
     }
 }
 
-impl ToString for LitType {
-    fn to_string(&self) -> String {
+impl fmt::Display for LitType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            LitType::Identifier(ref name) => String::from(name),
-            LitType::String(ref s) => String::from(s),
-            LitType::Number(ref x) => x.to_string(),
-            LitType::False => "false".to_string(),
-            LitType::True => "true".to_string(),
-            LitType::Nil => "nil".to_string(),
+            LitType::Identifier(ref name) => write!(f, "{}", name),
+            LitType::String(ref s) => write!(f, "{}", s),
+            LitType::Number(ref x) => write!(f, "{}", x),
+            LitType::False => write!(f, "false"),
+            LitType::True => write!(f, "true"),
+            LitType::Nil => write!(f, "nil"),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum KwordType {
     And,
     Class,
@@ -104,7 +108,53 @@ pub enum KwordType {
     While,
 }
 
-#[derive(Debug, PartialEq)]
+impl KwordType {
+    pub const ALL: [KwordType; 16] = [
+        KwordType::And,
+        KwordType::Class,
+        KwordType::Else,
+        KwordType::False,
+        KwordType::Fun,
+        KwordType::For,
+        KwordType::If,
+        KwordType::Nil,
+        KwordType::Or,
+        KwordType::Print,
+        KwordType::Return,
+        KwordType::Super,
+        KwordType::This,
+        KwordType::True,
+        KwordType::Var,
+        KwordType::While,
+    ];
+}
+
+impl fmt::Display for KwordType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kword = match self {
+            KwordType::And => "and",
+            KwordType::Class => "class",
+            KwordType::Else => "else",
+            KwordType::False => "false",
+            KwordType::Fun => "fun",
+            KwordType::For => "for",
+            KwordType::If => "if",
+            KwordType::Nil => "nil",
+            KwordType::Or => "or",
+            KwordType::Print => "print",
+            KwordType::Return => "return",
+            KwordType::Super => "super",
+            KwordType::This => "this",
+            KwordType::True => "true",
+            KwordType::Var => "var",
+            KwordType::While => "while",
+        };
+
+        write!(f, "{}", kword)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum TokenType {
     Op(OpType),
     Lit(LitType),
@@ -112,14 +162,32 @@ pub enum TokenType {
     Eof,
 }
 
-#[derive(Debug, PartialEq)]
+// A location in the source code
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Span {
+    // Line and column of the first character, counting from 1
+    pub line: usize,
+    pub column: usize,
+    // Offset of the first byte from the start of the source, and the length in bytes
+    pub byte_offset: usize,
+    pub len: usize,
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct Token {
     pub token_type: TokenType,
+    // The source text the token was lexed from
+    pub lexeme: String,
+    pub span: Span,
 }
 
 impl Token {
-    pub fn new(token_type: TokenType) -> Self {
-        Token { token_type }
+    pub fn new(token_type: TokenType, lexeme: String, span: Span) -> Self {
+        Token {
+            token_type,
+            lexeme,
+            span,
+        }
     }
 
     pub fn op_type(&self) -> Option<&OpType> {
@@ -144,29 +212,83 @@ impl Token {
     }
 }
 
-pub struct LineLexer {
-    line: Vec<char>,
+// The kinds of error that can be found while lexing
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LexErrorKind {
+    UnexpectedCharacter,
+    UnterminatedString,
+}
+
+impl fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LexErrorKind::UnexpectedCharacter => write!(f, "Unexpected character."),
+            LexErrorKind::UnterminatedString => write!(f, "Unterminated string."),
+        }
+    }
+}
+
+// An error found while lexing, along with where it was found
+#[derive(Debug, PartialEq, Clone)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub span: Span,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+// Lexes a whole source file, tracking the position of every token
+pub struct Lexer {
+    src: Vec<char>,
     position: usize,
     curr_char: char,
+    // Position of the next char to be consumed
+    line: usize,
+    column: usize,
+    byte_offset: usize,
+    // Position where the token being lexed starts
+    start: usize,
+    start_line: usize,
+    start_column: usize,
+    start_byte_offset: usize,
 }
 
-impl LineLexer {
-    pub fn new(line: String) -> Self {
-        let src_line: Vec<char> = line.trim().chars().collect();
-        let curr_char = src_line.clone().into_iter().next().unwrap_or('\0');
-
-        LineLexer {
-            line: src_line,
+impl Lexer {
+    pub fn new(src: &str) -> Self {
+        Lexer {
+            src: src.chars().collect(),
             position: 0,
-            curr_char,
+            curr_char: '\0',
+            line: 1,
+            column: 1,
+            byte_offset: 0,
+            start: 0,
+            start_line: 1,
+            start_column: 1,
+            start_byte_offset: 0,
         }
     }
 
-    pub fn lex_tokens(&mut self) -> Vec<Result<Token, String>> {
+    pub fn lex_tokens(&mut self) -> Vec<Result<Token, LexError>> {
         let mut tokens = Vec::new();
-        let src_size = self.line.len();
 
-        while self.position < src_size {
+        loop {
+            while let Some(c) = self.peek() {
+                if c.is_whitespace() {
+                    self.consume_char();
+                } else {
+                    break;
+                }
+            }
+
+            if self.peek().is_none() {
+                break;
+            }
+
             let token = self.lex_token();
 
             // Ignore comments
@@ -179,19 +301,14 @@ impl LineLexer {
             tokens.push(token);
         }
 
-        tokens.push(Ok(Token::new(TokenType::Eof)));
+        self.start_token();
+        tokens.push(Ok(self.make_token(TokenType::Eof)));
 
         tokens
     }
 
-    fn lex_token(&mut self) -> Result<Token, String> {
-        while let Some(c) = self.peek() {
-            if c.is_whitespace() {
-                self.consume_char();
-            } else {
-                break;
-            }
-        }
+    fn lex_token(&mut self) -> Result<Token, LexError> {
+        self.start_token();
 
         if self.is_op_char() {
             // Parse an operator
@@ -199,7 +316,7 @@ impl LineLexer {
         } else if self.is_num_char() {
             // Parse a number
             self.lex_num()
-        } else if self.peek().is_some() && self.peek().unwrap() == &'"' {
+        } else if self.peek() == Some(&'"') {
             // Parse a string
             self.lex_str()
         } else {
@@ -218,93 +335,63 @@ impl LineLexer {
 
     fn is_num_char(&self) -> bool {
         if let Some(c) = self.peek() {
-            c.is_numeric()
+            c.is_ascii_digit()
         } else {
             false
         }
     }
 
-    fn lex_op(&mut self) -> Result<Token, String> {
+    fn lex_op(&mut self) -> Result<Token, LexError> {
         self.consume_char();
-        match self.curr_char {
+        let c = self.curr_char;
+        let op = match c {
             // Handle single-char operators
-            '(' => Ok(Token::new(TokenType::Op(OpType::LeftParen))),
-            ')' => Ok(Token::new(TokenType::Op(OpType::RightParen))),
-            '{' => Ok(Token::new(TokenType::Op(OpType::LeftBrace))),
-            '}' => Ok(Token::new(TokenType::Op(OpType::RightBrace))),
-            ',' => Ok(Token::new(TokenType::Op(OpType::Comma))),
-            '.' => Ok(Token::new(TokenType::Op(OpType::Dot))),
-            '-' => Ok(Token::new(TokenType::Op(OpType::Minus))),
-            '+' => Ok(Token::new(TokenType::Op(OpType::Plus))),
-            ';' => Ok(Token::new(TokenType::Op(OpType::Semicolon))),
-            '*' => Ok(Token::new(TokenType::Op(OpType::Star))),
+            '(' => OpType::LeftParen,
+            ')' => OpType::RightParen,
+            '{' => OpType::LeftBrace,
+            '}' => OpType::RightBrace,
+            ',' => OpType::Comma,
+            '.' => OpType::Dot,
+            '-' => OpType::Minus,
+            '+' => OpType::Plus,
+            ';' => OpType::Semicolon,
+            '*' => OpType::Star,
 
             // Handle multi-char operators
-            '!' => {
-                if let Some(next_char) = self.peek() {
-                    if next_char == &'=' {
-                        self.consume_char();
-                        return Ok(Token::new(TokenType::Op(OpType::BangEqual)));
-                    }
-                }
+            '!' if self.consume_if('=') => OpType::BangEqual,
+            '!' => OpType::Bang,
+            '=' if self.consume_if('=') => OpType::EqualEqual,
+            '=' => OpType::Equal,
+            '>' if self.consume_if('=') => OpType::GreaterEqual,
+            '>' => OpType::Greater,
+            '<' if self.consume_if('=') => OpType::LessEqual,
+            '<' => OpType::Less,
 
-                Ok(Token::new(TokenType::Op(OpType::Bang)))
-            }
-            '=' => {
-                if let Some(next_char) = self.peek() {
-                    if next_char == &'=' {
-                        self.consume_char();
-                        return Ok(Token::new(TokenType::Op(OpType::EqualEqual)));
-                    }
-                }
-
-                Ok(Token::new(TokenType::Op(OpType::Equal)))
-            }
-            '>' => {
-                if let Some(next_char) = self.peek() {
-                    if next_char == &'=' {
-                        self.consume_char();
-                        return Ok(Token::new(TokenType::Op(OpType::GreaterEqual)));
-                    }
-                }
-
-                Ok(Token::new(TokenType::Op(OpType::Greater)))
-            }
-            '<' => {
-                if let Some(next_char) = self.peek() {
-                    if next_char == &'=' {
-                        self.consume_char();
-                        return Ok(Token::new(TokenType::Op(OpType::LessEqual)));
-                    }
-                }
-
-                Ok(Token::new(TokenType::Op(OpType::Less)))
-            }
             // Handle comments
-            '/' => {
-                if let Some(next_char) = self.peek() {
-                    if next_char == &'/' {
-                        // Consume the rest of the source
-                        while let Some(_next_char) = self.peek() {
-                            self.consume_char();
-                        }
-
-                        return Ok(Token::new(TokenType::Op(OpType::SlashSlash)));
+            '/' if self.consume_if('/') => {
+                // Consume the rest of the line
+                while let Some(next_char) = self.peek() {
+                    if next_char == &'\n' {
+                        break;
                     }
+                    self.consume_char();
                 }
 
-                Ok(Token::new(TokenType::Op(OpType::Slash)))
+                OpType::SlashSlash
             }
+            '/' => OpType::Slash,
 
-            _ => Err("Failed to parse token".to_string()),
-        }
+            _ => unreachable!("{} is not an operator", c),
+        };
+
+        Ok(self.make_token(TokenType::Op(op)))
     }
 
-    fn lex_num(&mut self) -> Result<Token, String> {
+    fn lex_num(&mut self) -> Result<Token, LexError> {
         let mut digits: String = String::new();
 
         while let Some(c) = self.peek() {
-            if c.is_numeric() || c == &'.' {
+            if c.is_ascii_digit() {
                 digits.push(*c);
                 self.consume_char();
             } else {
@@ -312,18 +399,35 @@ impl LineLexer {
             }
         }
 
-        Ok(Token::new(TokenType::Lit(LitType::Number(
-            digits.parse::<f32>().unwrap(),
+        // A fractional part needs at least one digit after the '.'
+        let has_fraction = self.peek() == Some(&'.')
+            && self.src.get(self.position + 1).is_some_and(|c| c.is_ascii_digit());
+        if has_fraction {
+            self.consume_char();
+            digits.push('.');
+
+            while let Some(c) = self.peek() {
+                if c.is_ascii_digit() {
+                    digits.push(*c);
+                    self.consume_char();
+                } else {
+                    break;
+                }
+            }
+        }
+
+        Ok(self.make_token(TokenType::Lit(LitType::Number(
+            digits.parse::<f64>().unwrap(),
         ))))
     }
 
-    fn lex_str(&mut self) -> Result<Token, String> {
+    fn lex_str(&mut self) -> Result<Token, LexError> {
         let mut string_val = String::new();
 
         // Consume the quote character
         self.consume_char();
 
-        // Consume string
+        // Consume string, which may span multiple lines
         while let Some(c) = self.peek() {
             if c == &'"' {
                 break;
@@ -334,30 +438,30 @@ impl LineLexer {
         }
 
         if self.peek().is_none() {
-            return Err("Unclosed quotation".to_string());
+            return Err(self.make_error(LexErrorKind::UnterminatedString));
         }
 
         // Consume the second quote character
         self.consume_char();
 
-        Ok(Token::new(TokenType::Lit(LitType::String(string_val))))
+        Ok(self.make_token(TokenType::Lit(LitType::String(string_val))))
     }
 
-    fn lex_identifier_or_kword(&mut self) -> Result<Token, String> {
+    fn lex_identifier_or_kword(&mut self) -> Result<Token, LexError> {
         let mut identifier: String = String::new();
 
         while let Some(c) = self.peek() {
-            if c.is_alphanumeric() {
+            if c.is_alphanumeric() || c == &'_' {
                 identifier.push(*c);
                 self.consume_char();
-            } else if !c.is_whitespace() && !self.is_op_char() {
-                let illegal_ch = *c;
-                identifier.push(illegal_ch);
+            } else if !c.is_whitespace() && !self.is_op_char() && c != &'"' {
+                // The identifier so far is a token of its own, and the illegal character is
+                // reported when lexing the next one
+                if !identifier.is_empty() {
+                    break;
+                }
                 self.consume_char();
-                return Err(format!(
-                    "Invalid character in identifier {}: {}",
-                    identifier, illegal_ch
-                ));
+                return Err(self.make_error(LexErrorKind::UnexpectedCharacter));
             } else {
                 break;
             }
@@ -365,9 +469,9 @@ impl LineLexer {
 
         let maybe_kword_type = self.str_to_keyword_type(&identifier);
         if let Some(kword_type) = maybe_kword_type {
-            Ok(Token::new(TokenType::Kword(kword_type)))
+            Ok(self.make_token(TokenType::Kword(kword_type)))
         } else {
-            Ok(Token::new(TokenType::Lit(LitType::Identifier(identifier))))
+            Ok(self.make_token(TokenType::Lit(LitType::Identifier(identifier))))
         }
     }
 
@@ -397,15 +501,64 @@ impl LineLexer {
         Some(kword_type)
     }
 
-    // Return the next char in the line, consuming it.
+    // Mark the next char as the start of a new token
+    fn start_token(&mut self) {
+        self.start = self.position;
+        self.start_line = self.line;
+        self.start_column = self.column;
+        self.start_byte_offset = self.byte_offset;
+    }
+
+    // The span from the start of the current token to the last consumed char
+    fn span(&self) -> Span {
+        Span {
+            line: self.start_line,
+            column: self.start_column,
+            byte_offset: self.start_byte_offset,
+            len: self.byte_offset - self.start_byte_offset,
+        }
+    }
+
+    fn make_token(&self, token_type: TokenType) -> Token {
+        let lexeme = self.src[self.start..self.position].iter().collect();
+
+        Token::new(token_type, lexeme, self.span())
+    }
+
+    fn make_error(&self, kind: LexErrorKind) -> LexError {
+        LexError {
+            kind,
+            span: self.span(),
+        }
+    }
+
+    // Consume the next char, tracking which line and column it is on.
     // This function assumes boundary checks have already been done.
     fn consume_char(&mut self) {
-        self.curr_char = *self.line.get(self.position).unwrap();
+        self.curr_char = *self.src.get(self.position).unwrap();
         self.position += 1;
+        self.byte_offset += self.curr_char.len_utf8();
+
+        if self.curr_char == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+    }
+
+    // Consume the next char only if it is the expected one
+    fn consume_if(&mut self, expected: char) -> bool {
+        if self.peek() == Some(&expected) {
+            self.consume_char();
+            return true;
+        }
+
+        false
     }
 
     fn peek(&self) -> Option<&char> {
-        self.line.get(self.position)
+        self.src.get(self.position)
     }
 }
 
@@ -415,17 +568,17 @@ mod test_lexer {
 
     #[test]
     fn test_lex_tokens() {
-        let mut lexer = LineLexer::new("var myVar = 5;".to_string());
+        let mut lexer = Lexer::new("var myVar = 5;");
         let tokens = lexer.lex_tokens();
 
         assert_eq!(6, tokens.len());
 
-        let mut lexer = LineLexer::new("print \"hello\";".to_string());
+        let mut lexer = Lexer::new("print \"hello\";");
         let tokens = lexer.lex_tokens();
 
         assert_eq!(4, tokens.len());
 
-        let mut lexer = LineLexer::new("a*5".to_string());
+        let mut lexer = Lexer::new("a*5");
         let tokens = lexer.lex_tokens();
 
         assert_eq!(4, tokens.len());
@@ -436,7 +589,7 @@ mod test_lexer {
         // Try to lex a string and assert that it succeeds
         let str = "\"hello\"";
 
-        let mut lexer = LineLexer::new(str.to_string());
+        let mut lexer = Lexer::new(str);
         let tok = lexer.lex_str();
 
         assert!(tok.is_ok());
@@ -444,7 +597,7 @@ mod test_lexer {
         // Try to lex a bad string and assert that it fails
         let str = "\"hello";
 
-        let mut lexer = LineLexer::new(str.to_string());
+        let mut lexer = Lexer::new(str);
         let tok = lexer.lex_str();
 
         assert!(tok.is_err());
@@ -455,7 +608,7 @@ mod test_lexer {
         // Try to lex a string and assert that it succeeds
         let num = "32.1";
 
-        let mut lexer = LineLexer::new(num.to_string());
+        let mut lexer = Lexer::new(num);
         let tok = lexer.lex_num();
 
         assert!(tok.is_ok());
@@ -464,7 +617,7 @@ mod test_lexer {
     #[test]
     fn lex_kword() {
         let kword = "var";
-        let mut lexer = LineLexer::new(kword.to_string());
+        let mut lexer = Lexer::new(kword);
         let tok = lexer.lex_identifier_or_kword().unwrap();
         assert_eq!(TokenType::Kword(KwordType::Var), tok.token_type);
     }
@@ -478,13 +631,12 @@ mod test_lexer {
             ("a123", true),
             ("bcc2dd", true),
             // Invalid identifiers
-            ("abc@", false),
             ("@#aa", false),
         ];
 
         // Run tests for each input
         for (identifier, is_valid) in test_inputs {
-            let mut lexer = LineLexer::new(identifier.to_string());
+            let mut lexer = Lexer::new(identifier);
             let result = lexer.lex_identifier_or_kword();
             if is_valid {
                 // Assert the success type of lexing valid identifiers
@@ -503,7 +655,7 @@ mod test_lexer {
     #[test]
     fn lex_comment() {
         let src = "var // this is a comment";
-        let mut lexer = LineLexer::new(src.to_string());
+        let mut lexer = Lexer::new(src);
         let tokens = lexer.lex_tokens();
 
         // Two tokens: var, EOF
@@ -513,7 +665,7 @@ mod test_lexer {
     #[test]
     fn test_empty() {
         let src = "";
-        let mut lexer = LineLexer::new(src.to_string());
+        let mut lexer = Lexer::new(src);
         let tokens = lexer.lex_tokens();
 
         // Only EOF token should be present
@@ -523,10 +675,88 @@ mod test_lexer {
     #[test]
     fn test_illegal_char() {
         let src = "illegal?";
-        let mut lexer = LineLexer::new(src.to_string());
+        let mut lexer = Lexer::new(src);
         let tokens = lexer.lex_tokens();
 
-        assert_eq!(2, tokens.len());
-        assert!(tokens.get(0).unwrap().is_err());
+        assert_eq!(3, tokens.len());
+        assert!(tokens[0].is_ok());
+        assert!(tokens[1].is_err());
+    }
+
+    #[test]
+    fn test_illegal_char_after_identifier() {
+        let src = "abc@";
+        let mut lexer = Lexer::new(src);
+        let tokens = lexer.lex_tokens();
+
+        // The identifier is kept, and the error points at the illegal character alone
+        let identifier = tokens[0].as_ref().unwrap();
+        assert_eq!(
+            TokenType::Lit(LitType::Identifier("abc".to_string())),
+            identifier.token_type
+        );
+        assert_eq!(
+            Span { line: 1, column: 1, byte_offset: 0, len: 3 },
+            identifier.span
+        );
+
+        let err = tokens[1].as_ref().unwrap_err();
+        assert_eq!(LexErrorKind::UnexpectedCharacter, err.kind);
+        assert_eq!(
+            Span { line: 1, column: 4, byte_offset: 3, len: 1 },
+            err.span
+        );
+
+        assert_eq!(TokenType::Eof, tokens[2].as_ref().unwrap().token_type);
+    }
+
+    #[test]
+    fn test_spans() {
+        let src = "var a = 1;\n  print \"hi\";";
+        let mut lexer = Lexer::new(src);
+        let tokens: Vec<Token> = lexer.lex_tokens().into_iter().map(|t| t.unwrap()).collect();
+
+        assert_eq!(
+            Span { line: 1, column: 5, byte_offset: 4, len: 1 },
+            tokens[1].span
+        );
+        assert_eq!("a", tokens[1].lexeme);
+
+        // Columns restart on each new line
+        assert_eq!(
+            Span { line: 2, column: 3, byte_offset: 13, len: 5 },
+            tokens[5].span
+        );
+        assert_eq!(
+            Span { line: 2, column: 9, byte_offset: 19, len: 4 },
+            tokens[6].span
+        );
+        assert_eq!("\"hi\"", tokens[6].lexeme);
+
+        // EOF sits just past the last character
+        assert_eq!(TokenType::Eof, tokens[8].token_type);
+        assert_eq!(
+            Span { line: 2, column: 14, byte_offset: 24, len: 0 },
+            tokens[8].span
+        );
+    }
+
+    #[test]
+    fn test_multi_line() {
+        let src = "\"one\ntwo\" // comment\nident";
+        let mut lexer = Lexer::new(src);
+        let tokens = lexer.lex_tokens();
+
+        // Strings may span lines, and comments end at the end of their line
+        assert_eq!(3, tokens.len());
+        let ident = tokens[1].as_ref().unwrap();
+        assert_eq!(TokenType::Lit(LitType::Identifier("ident".to_string())), ident.token_type);
+        assert_eq!(3, ident.span.line);
+
+        // Errors carry the location of the unterminated string
+        let mut lexer = Lexer::new("\n  \"abc");
+        let err = lexer.lex_tokens().remove(0).unwrap_err();
+        assert_eq!(LexErrorKind::UnterminatedString, err.kind);
+        assert_eq!((2, 3), (err.span.line, err.span.column));
     }
 }
@@ -1,4 +1,16 @@
+pub mod callable;
+pub mod chunk;
+pub mod class;
+pub mod compiler;
+pub mod diagnostics;
+pub mod disassembler;
+pub mod environment;
+pub mod heap;
 pub mod interpreter;
 pub mod lexer;
+pub mod loxc;
 pub mod parser;
+pub mod printer;
 pub mod repl;
+pub mod resolver;
+pub mod vm;
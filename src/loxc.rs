@@ -0,0 +1,750 @@
+use crate::chunk::{Chunk, OpCode};
+use crate::heap::{Function, Heap, Obj, ObjRef, Value};
+use crate::lexer::Span;
+use std::collections::BTreeSet;
+use std::fmt;
+
+// Compiled scripts are stored in .loxc files, laid out as:
+//
+//   magic      5 bytes   "\x7fLOXC"
+//   version    u16       FORMAT_VERSION
+//   checksum   u32       CRC-32 of the payload
+//   payload              the script's function, with nested functions inline in its constants
+//
+// Integers are little-endian. The version must change whenever the payload or the meaning of
+// the bytecode does.
+//
+// The checksum only catches accidents, so each function's code is also verified as it is
+// loaded, so that no file can make the VM index past its code, constants, upvalues or stack
+// frame, or leave an upvalue pointing at a slot that has been popped. The kinds of value
+// instructions work on can't be known before running, so the VM checks them itself wherever a
+// file could leave the wrong kind, raising a runtime error instead of panicking.
+
+const MAGIC: &[u8; 5] = b"\x7fLOXC";
+pub const FORMAT_VERSION: u16 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 2 + 4;
+
+// How each constant in a chunk is tagged
+const TAG_NUMBER: u8 = 0;
+const TAG_STRING: u8 = 1;
+const TAG_FUNCTION: u8 = 2;
+
+// Why a compiled script couldn't be loaded
+#[derive(Debug, PartialEq)]
+pub enum LoadError {
+    NotCompiled,
+    UnsupportedVersion(u16),
+    ChecksumMismatch,
+    Malformed(&'static str),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::NotCompiled => write!(f, "Not a compiled Lox script."),
+            LoadError::UnsupportedVersion(version) => write!(
+                f,
+                "Compiled with bytecode format version {}, but this rlox only runs version {}. \
+                 Recompile it from source.",
+                version, FORMAT_VERSION
+            ),
+            LoadError::ChecksumMismatch => write!(f, "Checksum mismatch; the file is corrupt."),
+            LoadError::Malformed(what) => write!(f, "Malformed bytecode: {}.", what),
+        }
+    }
+}
+
+// Whether some bytes look like a compiled script rather than source code
+pub fn is_compiled(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+// Serialize a compiled script
+pub fn write(script: ObjRef, heap: &Heap) -> Vec<u8> {
+    let mut payload = Vec::new();
+    write_function(&mut payload, heap.function(script), heap);
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&crc32(&payload).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    bytes
+}
+
+// Load a compiled script onto a heap, returning its function
+pub fn read(bytes: &[u8], heap: &mut Heap) -> Result<ObjRef, LoadError> {
+    if !is_compiled(bytes) {
+        return Err(LoadError::NotCompiled);
+    }
+
+    // The version is checked first, as anything after it may be laid out differently
+    let mut header = Reader {
+        bytes,
+        offset: MAGIC.len(),
+    };
+    let version = header.u16()?;
+    if version != FORMAT_VERSION {
+        return Err(LoadError::UnsupportedVersion(version));
+    }
+    let checksum = header.u32()?;
+    let payload = &bytes[HEADER_LEN..];
+    if crc32(payload) != checksum {
+        return Err(LoadError::ChecksumMismatch);
+    }
+
+    let mut reader = Reader {
+        bytes: payload,
+        offset: 0,
+    };
+    let function = reader.function(heap)?;
+    if reader.offset != payload.len() {
+        return Err(LoadError::Malformed("unexpected data after the script"));
+    }
+
+    // The VM calls the script with nothing to pass it and nothing for it to capture
+    if function.name.is_some() {
+        return Err(LoadError::Malformed("the script has a name"));
+    }
+    if function.arity != 0 {
+        return Err(LoadError::Malformed("the script takes parameters"));
+    }
+    if function.upvalue_count != 0 {
+        return Err(LoadError::Malformed("the script captures variables"));
+    }
+
+    Ok(heap.alloc(Obj::Function(function)))
+}
+
+fn write_u32(out: &mut Vec<u8>, n: usize) {
+    out.extend_from_slice(&(n as u32).to_le_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_u32(out, s.len());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_function(out: &mut Vec<u8>, function: &Function, heap: &Heap) {
+    match function.name {
+        Some(name) => {
+            out.push(1);
+            write_str(out, heap.string(name));
+        }
+        None => out.push(0),
+    }
+    write_u32(out, function.arity);
+    write_u32(out, function.upvalue_count);
+
+    let chunk = &function.chunk;
+    write_u32(out, chunk.code.len());
+    out.extend_from_slice(&chunk.code);
+
+    write_u32(out, chunk.constants.len());
+    for &constant in &chunk.constants {
+        match constant {
+            Value::Number(n) => {
+                out.push(TAG_NUMBER);
+                out.extend_from_slice(&n.to_le_bytes());
+            }
+            Value::Obj(obj) => match heap.get(obj) {
+                Obj::String(s) => {
+                    out.push(TAG_STRING);
+                    write_str(out, s);
+                }
+                Obj::Function(function) => {
+                    out.push(TAG_FUNCTION);
+                    write_function(out, function, heap);
+                }
+                other => unreachable!(
+                    "Functions are the only objects in constants, found {:?}",
+                    other
+                ),
+            },
+            other => unreachable!("The compiler never stores {:?} as a constant", other),
+        }
+    }
+
+    write_u32(out, chunk.spans().len());
+    for &(offset, span) in chunk.spans() {
+        for n in [offset, span.line, span.column, span.byte_offset, span.len] {
+            write_u32(out, n);
+        }
+    }
+}
+
+// Reads a serialized script, failing if it ends early
+struct Reader<'b> {
+    bytes: &'b [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], LoadError> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset.saturating_add(len))
+            .ok_or(LoadError::Malformed("unexpected end of file"))?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn usize(&mut self) -> Result<usize, LoadError> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn string(&mut self, heap: &mut Heap) -> Result<ObjRef, LoadError> {
+        let len = self.usize()?;
+        let s = std::str::from_utf8(self.take(len)?)
+            .map_err(|_| LoadError::Malformed("a string isn't valid UTF-8"))?;
+        Ok(heap.intern(s))
+    }
+
+    fn function(&mut self, heap: &mut Heap) -> Result<Function, LoadError> {
+        let name = match self.u8()? {
+            0 => None,
+            1 => Some(self.string(heap)?),
+            _ => return Err(LoadError::Malformed("invalid function name")),
+        };
+        let arity = self.usize()?;
+        let upvalue_count = self.usize()?;
+
+        let len = self.usize()?;
+        let code = self.take(len)?.to_vec();
+
+        let count = self.usize()?;
+        let mut constants = Vec::new();
+        for _ in 0..count {
+            let constant = match self.u8()? {
+                TAG_NUMBER => Value::Number(f64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+                TAG_STRING => Value::Obj(self.string(heap)?),
+                TAG_FUNCTION => {
+                    let function = self.function(heap)?;
+                    Value::Obj(heap.alloc(Obj::Function(function)))
+                }
+                _ => return Err(LoadError::Malformed("invalid constant")),
+            };
+            constants.push(constant);
+        }
+
+        let count = self.usize()?;
+        let mut spans = Vec::new();
+        for _ in 0..count {
+            let offset = self.usize()?;
+            let span = Span {
+                line: self.usize()?,
+                column: self.usize()?,
+                byte_offset: self.usize()?,
+                len: self.usize()?,
+            };
+            spans.push((offset, span));
+        }
+        if len > 0 && spans.first().is_none_or(|&(offset, _)| offset != 0) {
+            return Err(LoadError::Malformed("missing line information"));
+        }
+
+        let function = Function {
+            name,
+            arity,
+            upvalue_count,
+            chunk: Chunk::from_parts(code, constants, spans),
+        };
+        verify(&function, heap)?;
+        Ok(function)
+    }
+}
+
+// Check that a function's code is safe for the VM to run: every instruction is whole and
+// refers to constants and upvalues that exist, jumps land on instructions, every path through
+// the code keeps the same stack depth without popping into the caller's frame, and locals
+// captured by closures are only popped by closing them
+fn verify(function: &Function, heap: &Heap) -> Result<(), LoadError> {
+    let code = &function.chunk.code;
+
+    let mut starts = vec![false; code.len()];
+    let mut offset = 0;
+    while offset < code.len() {
+        starts[offset] = true;
+        offset += check_instruction(function, offset, heap)?;
+    }
+
+    // The depth of the stack above the frame's slots at each instruction reached so far, and
+    // the slots that may have been captured by a closure on the way there. Slot zero holds
+    // the callee and the arguments follow it. An instruction is checked again whenever another
+    // path reaches it with more slots captured.
+    let mut states: Vec<Option<(usize, BTreeSet<usize>)>> = vec![None; code.len()];
+    let mut pending = vec![(0, function.arity + 1, BTreeSet::new())];
+    while let Some((offset, depth, mut captured)) = pending.pop() {
+        if offset >= code.len() {
+            return Err(LoadError::Malformed("the code runs past its end"));
+        }
+        if !starts[offset] {
+            return Err(LoadError::Malformed("a jump lands inside an instruction"));
+        }
+        match &mut states[offset] {
+            Some((seen, _)) if *seen != depth => {
+                return Err(LoadError::Malformed(
+                    "the stack depth differs between paths",
+                ))
+            }
+            Some((_, seen)) if captured.is_subset(seen) => continue,
+            Some((_, seen)) => {
+                captured.extend(seen.iter());
+                *seen = captured.clone();
+            }
+            state @ None => *state = Some((depth, captured.clone())),
+        }
+
+        let op = OpCode::try_from(code[offset]).unwrap();
+        let operand = |i: usize| code[offset + i] as usize;
+        let (pops, pushes) = stack_effect(op, operand);
+        if pops > depth {
+            return Err(LoadError::Malformed("the stack underflows"));
+        }
+        match op {
+            OpCode::CloseUpvalue => {
+                captured.remove(&(depth - 1));
+            }
+            OpCode::Return => {}
+            _ if captured.range(depth - pops..).next().is_some() => {
+                return Err(LoadError::Malformed(
+                    "a captured local is popped without being closed",
+                ));
+            }
+            _ => {}
+        }
+
+        let mut next = offset + 1 + op.operand_len();
+        match op {
+            OpCode::GetLocal | OpCode::SetLocal if operand(1) >= depth => {
+                return Err(LoadError::Malformed("a local slot is out of range"));
+            }
+            OpCode::Closure => {
+                let Value::Obj(inner) = function.chunk.constants[operand(1)] else {
+                    unreachable!("Checked to be a function")
+                };
+                for _ in 0..heap.function(inner).upvalue_count {
+                    if code[next] == 1 {
+                        let slot = code[next + 1] as usize;
+                        if slot >= depth {
+                            return Err(LoadError::Malformed("a captured local is out of range"));
+                        }
+                        captured.insert(slot);
+                    }
+                    next += 2;
+                }
+            }
+            _ => {}
+        }
+
+        let depth = depth - pops + pushes;
+        let jump = || u16::from_be_bytes([code[offset + 1], code[offset + 2]]) as usize;
+        match op {
+            OpCode::Return => {}
+            OpCode::Jump => pending.push((next + jump(), depth, captured)),
+            OpCode::JumpIfFalse => {
+                pending.push((next, depth, captured.clone()));
+                pending.push((next + jump(), depth, captured));
+            }
+            OpCode::Loop => {
+                let target = next.checked_sub(jump()).ok_or(LoadError::Malformed(
+                    "a loop jumps before the start of the code",
+                ))?;
+                pending.push((target, depth, captured));
+            }
+            _ => pending.push((next, depth, captured)),
+        }
+    }
+
+    Ok(())
+}
+
+// Check the instruction at an offset is whole and its operands refer to things that exist,
+// returning its length
+fn check_instruction(function: &Function, offset: usize, heap: &Heap) -> Result<usize, LoadError> {
+    let chunk = &function.chunk;
+    let op =
+        OpCode::try_from(chunk.code[offset]).map_err(|_| LoadError::Malformed("unknown opcode"))?;
+    let operands = chunk
+        .code
+        .get(offset + 1..offset + 1 + op.operand_len())
+        .ok_or(LoadError::Malformed("an instruction is cut short"))?;
+
+    let constant = || {
+        chunk
+            .constants
+            .get(operands[0] as usize)
+            .ok_or(LoadError::Malformed("a constant index is out of range"))
+    };
+
+    match op {
+        OpCode::Constant => {
+            constant()?;
+        }
+        OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::GetSuper
+        | OpCode::Invoke
+        | OpCode::SuperInvoke
+        | OpCode::Class
+        | OpCode::Method => match *constant()? {
+            Value::Obj(name) if matches!(heap.get(name), Obj::String(_)) => {}
+            _ => return Err(LoadError::Malformed("a name constant isn't a string")),
+        },
+        OpCode::GetUpvalue | OpCode::SetUpvalue
+            if operands[0] as usize >= function.upvalue_count =>
+        {
+            return Err(LoadError::Malformed("an upvalue index is out of range"));
+        }
+        OpCode::Closure => {
+            let captured = match *constant()? {
+                Value::Obj(captured) if matches!(heap.get(captured), Obj::Function(_)) => captured,
+                _ => {
+                    return Err(LoadError::Malformed(
+                        "a closure's constant isn't a function",
+                    ))
+                }
+            };
+
+            // Each captured variable is a local of this function or one of its upvalues
+            let count = heap.function(captured).upvalue_count;
+            let start = offset + 2;
+            let captures = chunk
+                .code
+                .get(start..start + 2 * count)
+                .ok_or(LoadError::Malformed("an instruction is cut short"))?;
+            for capture in captures.chunks(2) {
+                match capture[0] {
+                    1 => {}
+                    0 if (capture[1] as usize) < function.upvalue_count => {}
+                    0 => return Err(LoadError::Malformed("an upvalue index is out of range")),
+                    _ => return Err(LoadError::Malformed("invalid captured variable")),
+                }
+            }
+            return Ok(2 + 2 * count);
+        }
+        _ => {}
+    }
+
+    Ok(1 + op.operand_len())
+}
+
+// How many values an instruction needs on the stack and how many it leaves in their place.
+// Values an instruction only looks at count as taken and put back.
+fn stack_effect(op: OpCode, operand: impl Fn(usize) -> usize) -> (usize, usize) {
+    match op {
+        OpCode::Constant
+        | OpCode::Nil
+        | OpCode::True
+        | OpCode::False
+        | OpCode::GetLocal
+        | OpCode::GetGlobal
+        | OpCode::GetUpvalue
+        | OpCode::Closure
+        | OpCode::Class => (0, 1),
+        OpCode::Jump | OpCode::Loop => (0, 0),
+        OpCode::Pop
+        | OpCode::DefineGlobal
+        | OpCode::Print
+        | OpCode::CloseUpvalue
+        | OpCode::Return => (1, 0),
+        OpCode::SetLocal
+        | OpCode::SetGlobal
+        | OpCode::SetUpvalue
+        | OpCode::GetProperty
+        | OpCode::Not
+        | OpCode::Negate
+        | OpCode::JumpIfFalse => (1, 1),
+        OpCode::SetProperty
+        | OpCode::GetSuper
+        | OpCode::Equal
+        | OpCode::Greater
+        | OpCode::GreaterEqual
+        | OpCode::Less
+        | OpCode::LessEqual
+        | OpCode::Add
+        | OpCode::Subtract
+        | OpCode::Multiply
+        | OpCode::Divide
+        | OpCode::Inherit
+        | OpCode::Method => (2, 1),
+        // The callee or receiver sits below the arguments, and super calls also take the
+        // superclass
+        OpCode::Call => (operand(1) + 1, 1),
+        OpCode::Invoke => (operand(2) + 1, 1),
+        OpCode::SuperInvoke => (operand(2) + 2, 1),
+    }
+}
+
+// The CRC-32 used by zip and PNG, computed a bit at a time as scripts are small
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test_loxc {
+    use super::*;
+    use crate::compiler;
+    use crate::disassembler::disassemble;
+    use crate::interpreter::{self, LoxError, RuntimeErrorKind};
+    use crate::vm::Vm;
+
+    fn compile(heap: &mut Heap, src: &str) -> ObjRef {
+        let (stmts, _) = interpreter::compile(src).unwrap();
+        compiler::compile(&stmts, heap).unwrap()
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(0, crc32(b""));
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let src = "
+            class A { init(x) { this.x = x; } }
+            class B < A { get() { return this.x * 1.5; } }
+            fun make(n) { fun inner() { return n + \"!\"; } return inner; }
+            print B(2).get();
+            print make(\"hi\")();
+        ";
+        let mut heap = Heap::new();
+        let script = compile(&mut heap, src);
+        let bytes = write(script, &heap);
+        assert!(is_compiled(&bytes));
+
+        // Loading onto a fresh heap gives back the same code, constants and lines
+        let mut loaded_heap = Heap::new();
+        let loaded = read(&bytes, &mut loaded_heap).unwrap();
+        assert_eq!(
+            disassemble(script, &heap),
+            disassemble(loaded, &loaded_heap)
+        );
+    }
+
+    #[test]
+    fn test_rejected_files() {
+        let mut heap = Heap::new();
+        let script = compile(&mut heap, "print 1;");
+        let bytes = write(script, &heap);
+
+        assert_eq!(Err(LoadError::NotCompiled), read(b"print 1;", &mut heap));
+
+        let mut newer = bytes.clone();
+        newer[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&2u16.to_le_bytes());
+        assert_eq!(
+            Err(LoadError::UnsupportedVersion(2)),
+            read(&newer, &mut heap)
+        );
+
+        let mut corrupt = bytes.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert_eq!(Err(LoadError::ChecksumMismatch), read(&corrupt, &mut heap));
+
+        // A payload that ends early is caught even with a matching checksum
+        let mut truncated = bytes[..bytes.len() - 4].to_vec();
+        let checksum = crc32(&truncated[HEADER_LEN..]);
+        truncated[MAGIC.len() + 2..HEADER_LEN].copy_from_slice(&checksum.to_le_bytes());
+        assert_eq!(
+            Err(LoadError::Malformed("unexpected end of file")),
+            read(&truncated, &mut heap)
+        );
+    }
+
+    // A function built by hand, as if it had been compiled
+    fn function(code: &[u8], constants: Vec<Value>) -> Function {
+        let spans = vec![(0, Span::default())];
+        Function {
+            chunk: Chunk::from_parts(code.to_vec(), constants, spans),
+            ..Function::default()
+        }
+    }
+
+    // Serialize a script built by hand and load it back
+    fn load(script: Function, heap: &mut Heap) -> Result<ObjRef, LoadError> {
+        let script = heap.alloc(Obj::Function(script));
+        read(&write(script, heap), heap)
+    }
+
+    // Load a script built by hand, whose only constant is the name "x", and run it on the VM
+    fn run(code: &[u8]) -> Result<(), RuntimeErrorKind> {
+        let mut vm = Vm::new();
+        let name = Value::Obj(vm.heap_mut().intern("x"));
+        let script = load(function(code, vec![name]), vm.heap_mut()).unwrap();
+        match vm.run_script(script) {
+            Ok(()) => Ok(()),
+            Err(LoxError::Runtime(err)) => Err(err.kind),
+            Err(err) => panic!("Expected a runtime error, got {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        // A valid checksum doesn't stop a constant index from pointing past the constants
+        let mut heap = Heap::new();
+        let script = compile(&mut heap, "print 1;");
+        let mut bytes = write(script, &heap);
+        let operand = HEADER_LEN + 1 + 4 + 4 + 4 + 1;
+        assert_eq!(0, bytes[operand]);
+        bytes[operand] = 5;
+        let checksum = crc32(&bytes[HEADER_LEN..]);
+        bytes[MAGIC.len() + 2..HEADER_LEN].copy_from_slice(&checksum.to_le_bytes());
+        assert_eq!(
+            Err(LoadError::Malformed("a constant index is out of range")),
+            read(&bytes, &mut heap)
+        );
+
+        use OpCode::*;
+        let malformed = |script: Function| match load(script, &mut Heap::new()) {
+            Err(LoadError::Malformed(what)) => what,
+            other => panic!("Expected the script not to load, got {:?}", other),
+        };
+        let op = |op: OpCode| op as u8;
+
+        assert!(load(function(&[op(Nil), op(Return)], vec![]), &mut Heap::new()).is_ok());
+        assert_eq!("unknown opcode", malformed(function(&[39], vec![])));
+        assert_eq!(
+            "an instruction is cut short",
+            malformed(function(&[op(Constant)], vec![]))
+        );
+        assert_eq!(
+            "the code runs past its end",
+            malformed(function(&[op(Nil)], vec![]))
+        );
+        assert_eq!(
+            "a name constant isn't a string",
+            malformed(function(
+                &[op(GetGlobal), 0, op(Return)],
+                vec![Value::Number(1.0)]
+            ))
+        );
+        assert_eq!(
+            "an upvalue index is out of range",
+            malformed(function(&[op(GetUpvalue), 0, op(Return)], vec![]))
+        );
+        assert_eq!(
+            "a jump lands inside an instruction",
+            malformed(function(
+                &[op(Jump), 0, 1, op(Constant), 0, op(Return)],
+                vec![Value::Number(1.0)]
+            ))
+        );
+        assert_eq!(
+            "a loop jumps before the start of the code",
+            malformed(function(&[op(Loop), 0, 9], vec![]))
+        );
+
+        // Slot zero holds the script itself, so there is one value to start with
+        assert_eq!(
+            "the stack underflows",
+            malformed(function(&[op(Add), op(Return)], vec![]))
+        );
+        assert_eq!(
+            "a local slot is out of range",
+            malformed(function(&[op(GetLocal), 1, op(Return)], vec![]))
+        );
+        assert_eq!(
+            "the stack depth differs between paths",
+            malformed(function(
+                &[op(Nil), op(JumpIfFalse), 0, 1, op(Nil), op(Return)],
+                vec![]
+            ))
+        );
+
+        // The VM calls the script with no arguments and nothing captured
+        let mut heap = Heap::new();
+        let named = Function {
+            name: Some(heap.intern("script")),
+            ..function(&[op(Nil), op(Return)], vec![])
+        };
+        assert_eq!(
+            Err(LoadError::Malformed("the script has a name")),
+            load(named, &mut heap)
+        );
+        assert_eq!(
+            "the script takes parameters",
+            malformed(Function {
+                arity: 1,
+                ..function(&[op(Nil), op(Return)], vec![])
+            })
+        );
+        assert_eq!(
+            "the script captures variables",
+            malformed(Function {
+                upvalue_count: 1,
+                ..function(&[op(GetUpvalue), 0, op(Return)], vec![])
+            })
+        );
+
+        // An upvalue left open on a popped slot would point past the stack
+        let inner = Value::Obj(heap.alloc(Obj::Function(Function {
+            upvalue_count: 1,
+            ..function(&[op(GetUpvalue), 0, op(Return)], vec![])
+        })));
+        let capture = |closing: OpCode| {
+            function(
+                &[
+                    op(Nil),
+                    op(Closure),
+                    0,
+                    1,
+                    1,
+                    op(Pop),
+                    op(closing),
+                    op(Nil),
+                    op(Return),
+                ],
+                vec![inner],
+            )
+        };
+        assert!(load(capture(CloseUpvalue), &mut heap).is_ok());
+        assert_eq!(
+            Err(LoadError::Malformed(
+                "a captured local is popped without being closed"
+            )),
+            load(capture(Pop), &mut heap)
+        );
+
+        // Values of the wrong kind are runtime errors rather than panics
+        assert_eq!(
+            Err(RuntimeErrorKind::SuperclassMustBeClass),
+            run(&[op(Nil), op(GetSuper), 0, op(Return)])
+        );
+        assert_eq!(
+            Err(RuntimeErrorKind::SuperclassMustBeClass),
+            run(&[op(Nil), op(Nil), op(SuperInvoke), 0, 0, op(Return)])
+        );
+        assert_eq!(
+            Err(RuntimeErrorKind::MalformedBytecode),
+            run(&[op(Class), 0, op(Nil), op(Inherit), op(Return)])
+        );
+        assert_eq!(
+            Err(RuntimeErrorKind::MalformedBytecode),
+            run(&[op(Class), 0, op(Nil), op(Method), 0, op(Return)])
+        );
+    }
+}
@@ -1,24 +1,309 @@
-mod interpreter;
-mod lexer;
-mod repl;
+use lox_interpreter::compiler;
+use lox_interpreter::diagnostics::{self, ErrorFormat};
+use lox_interpreter::disassembler::disassemble;
+use lox_interpreter::heap::{Heap, ObjRef};
+use lox_interpreter::interpreter::{self, Interpreter, LoxError};
+use lox_interpreter::loxc;
+use lox_interpreter::printer::{print_tokens, AstPrinter};
+use lox_interpreter::repl;
+use lox_interpreter::vm::Vm;
 
-use std::{env, fs};
+use std::path::Path;
+use std::{env, fs, panic, process, thread};
+
+// Exit codes from sysexits.h, as used by the reference implementation
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_NOINPUT: i32 = 66;
+const EX_SOFTWARE: i32 = 70;
+const EX_CANTCREAT: i32 = 73;
+
+// Room for the interpreter's recursion, only committed as it is used
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+const USAGE: &str = "Usage: rlox [--error-format=human|short|json] [--backend=tree|vm] \
+                     [--trace-execution] [--gc-stress] [--gc-log] [--dump-tokens | --dump-ast | --dump-bytecode] [script]\n       \
+                     rlox compile [--error-format=human|short|json] script [-o output]";
+
+// What to do with a script
+#[derive(PartialEq)]
+enum Mode {
+    Run,
+    // Stop after lexing and print the tokens
+    DumpTokens,
+    // Stop after parsing and print the syntax tree
+    DumpAst,
+    // Stop after compiling and print the bytecode
+    DumpBytecode,
+    // Compile to bytecode and write it to a file instead of running it
+    Compile(String),
+}
+
+// What runs a script
+#[derive(PartialEq, Clone, Copy)]
+enum Backend {
+    // The tree-walking interpreter
+    Tree,
+    // The bytecode compiler and virtual machine
+    Vm,
+}
+
+// Command line options
+struct Options {
+    script: Option<String>,
+    error_format: ErrorFormat,
+    // The backend asked for, if any
+    backend: Option<Backend>,
+    // Print each instruction the VM runs
+    trace_execution: bool,
+    // Collect garbage on every allocation the VM makes
+    gc_stress: bool,
+    // Print a line for each garbage collection
+    gc_log: bool,
+    mode: Mode,
+}
+
+impl Options {
+    fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options {
+            script: None,
+            error_format: ErrorFormat::Human,
+            backend: None,
+            trace_execution: false,
+            gc_stress: false,
+            gc_log: false,
+            mode: Mode::Run,
+        };
+
+        let mut args = args.peekable();
+        let compile = args.next_if_eq("compile").is_some();
+        let mut output = None;
+
+        while let Some(arg) = args.next() {
+            let mode = match arg.as_str() {
+                "--dump-tokens" => Some(Mode::DumpTokens),
+                "--dump-ast" => Some(Mode::DumpAst),
+                "--dump-bytecode" => Some(Mode::DumpBytecode),
+                _ => None,
+            };
+
+            if let Some(format) = arg.strip_prefix("--error-format=") {
+                options.error_format = format.parse()?;
+            } else if let Some(backend) = arg.strip_prefix("--backend=") {
+                options.backend = match backend {
+                    "tree" => Some(Backend::Tree),
+                    "vm" => Some(Backend::Vm),
+                    _ => return Err(format!("Unknown backend '{}'.", backend)),
+                };
+            } else if compile && arg == "-o" {
+                output = Some(args.next().ok_or("-o needs a file to write to.")?);
+            } else if arg == "--trace-execution" {
+                options.trace_execution = true;
+            } else if arg == "--gc-stress" {
+                options.gc_stress = true;
+            } else if arg == "--gc-log" {
+                options.gc_log = true;
+            } else if let Some(mode) = mode {
+                if options.mode != Mode::Run {
+                    return Err(
+                        "Only one of --dump-tokens, --dump-ast and --dump-bytecode can be given."
+                            .to_string(),
+                    );
+                }
+                options.mode = mode;
+            } else if arg.starts_with('-') {
+                return Err(format!("Unknown option '{}'.", arg));
+            } else if options.script.is_none() {
+                options.script = Some(arg);
+            } else {
+                return Err("Only one script can be run at a time.".to_string());
+            }
+        }
+
+        if compile {
+            let Some(ref script) = options.script else {
+                return Err("A script to compile must be given.".to_string());
+            };
+            if options.mode != Mode::Run || options.backend.is_some() || options.vm_only() {
+                return Err("Only --error-format and -o can be given when compiling.".to_string());
+            }
+
+            let output = output.unwrap_or_else(|| {
+                let path = Path::new(script).with_extension("loxc");
+                path.to_string_lossy().into_owned()
+            });
+            options.mode = Mode::Compile(output);
+        }
+
+        if options.mode != Mode::Run && options.script.is_none() {
+            return Err("A script to dump must be given.".to_string());
+        }
+        if options.vm_only() && options.backend == Some(Backend::Tree) {
+            return Err(
+                "--trace-execution, --gc-stress and --gc-log need the VM backend.".to_string(),
+            );
+        }
+        // The REPL only runs on the tree-walking interpreter
+        if options.backend() == Backend::Vm && options.script.is_none() {
+            return Err("A script to run on the VM must be given.".to_string());
+        }
+
+        Ok(options)
+    }
+
+    // Whether any options only the VM understands were given
+    fn vm_only(&self) -> bool {
+        self.trace_execution || self.gc_stress || self.gc_log
+    }
+
+    // The backend to run source code on. Options for the VM pick it unless told otherwise.
+    fn backend(&self) -> Backend {
+        match self.backend {
+            Some(backend) => backend,
+            None if self.vm_only() => Backend::Vm,
+            None => Backend::Tree,
+        }
+    }
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    process::exit(EX_USAGE);
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let options = Options::parse(env::args().skip(1)).unwrap_or_else(|e| usage_error(&e));
 
-    if args.len() > 1 {
-        println!("Usage: rlox [script]");
-    } else if args.len() == 2 {
-        run_script(args.get(1).unwrap());
-    } else {
-        repl::run_repl();
+    // The tree-walking interpreter recurses on the Rust stack for every Lox call, and in debug
+    // builds the main thread's stack runs out well before it reports a stack overflow itself
+    let run = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || match options.script {
+            Some(ref script_path) => run_script(script_path, &options),
+            None => repl::run_repl(),
+        })
+        .expect("Failed to start the interpreter thread");
+    if let Err(panic) = run.join() {
+        panic::resume_unwind(panic);
     }
 }
 
-fn run_script(script_path: &str) {
-    let script_str = fs::read_to_string(script_path)
-        .unwrap_or_else(|e| panic!("Failed to read input file {}: {}", script_path, e));
+// Run a script, exiting with a status that tells compile errors and runtime errors apart.
+// The script may be source code or compiled bytecode.
+fn run_script(script_path: &str, options: &Options) {
+    let read_failed = |e: &dyn std::fmt::Display| -> ! {
+        eprintln!("Failed to read input file {}: {}", script_path, e);
+        process::exit(EX_NOINPUT);
+    };
+    let bytes = fs::read(script_path).unwrap_or_else(|e| read_failed(&e));
+    if loxc::is_compiled(&bytes) {
+        return run_compiled(script_path, &bytes, options);
+    }
+    let script_str = String::from_utf8(bytes).unwrap_or_else(|e| read_failed(&e));
 
-    println!("{}", script_str);
+    let result = match options.mode {
+        Mode::Run => match options.backend() {
+            Backend::Tree => Interpreter::new().run(&script_str),
+            Backend::Vm => new_vm(options).run(&script_str),
+        },
+        Mode::DumpTokens => dump_tokens(&script_str),
+        Mode::DumpAst => dump_ast(&script_str),
+        Mode::DumpBytecode => dump_bytecode(&script_str),
+        Mode::Compile(ref output) => compile(&script_str, output),
+    };
+
+    if let Err(err) = result {
+        exit_with(&err, options, script_path, &script_str);
+    }
+}
+
+// Report an error and exit with a status that tells compile errors and runtime errors apart
+fn exit_with(err: &LoxError, options: &Options, script_path: &str, src: &str) -> ! {
+    diagnostics::report(err, options.error_format, script_path, src);
+    process::exit(match err {
+        LoxError::Compile(_) => EX_DATAERR,
+        LoxError::Runtime(_) => EX_SOFTWARE,
+    });
+}
+
+// A VM set up as the options ask
+fn new_vm(options: &Options) -> Vm {
+    let mut vm = Vm::new();
+    vm.set_trace(options.trace_execution);
+    vm.set_gc_stress(options.gc_stress);
+    vm.set_gc_log(options.gc_log);
+    vm
+}
+
+// Load and run a script compiled with 'rlox compile', which only the VM can run
+fn run_compiled(script_path: &str, bytes: &[u8], options: &Options) {
+    match options.mode {
+        Mode::Run | Mode::DumpBytecode => {}
+        Mode::Compile(_) => usage_error(&format!("{} is already compiled.", script_path)),
+        Mode::DumpTokens | Mode::DumpAst => {
+            usage_error("Compiled scripts have no tokens or syntax tree to dump.")
+        }
+    }
+    if options.backend == Some(Backend::Tree) {
+        usage_error("Compiled scripts can only run on the VM backend.");
+    }
+
+    let mut vm = new_vm(options);
+    let script = loxc::read(bytes, vm.heap_mut()).unwrap_or_else(|e| {
+        eprintln!("Failed to load compiled script {}: {}", script_path, e);
+        process::exit(EX_DATAERR);
+    });
+
+    if options.mode == Mode::DumpBytecode {
+        print!("{}", disassemble(script, vm.heap()));
+        return;
+    }
+
+    // There is no source to show where a runtime error happened, only its line and column
+    if let Err(err) = vm.run_script(script) {
+        exit_with(&err, options, script_path, "");
+    }
+}
+
+fn dump_tokens(src: &str) -> Result<(), LoxError> {
+    let (tokens, errors) = interpreter::lex(src);
+    if !errors.is_empty() {
+        return Err(LoxError::Compile(errors));
+    }
+
+    print!("{}", print_tokens(&tokens));
+    Ok(())
+}
+
+fn dump_ast(src: &str) -> Result<(), LoxError> {
+    let stmts = interpreter::parse(src).map_err(LoxError::Compile)?;
+
+    print!("{}", AstPrinter::new().print(&stmts));
+    Ok(())
+}
+
+// Compile source code to bytecode
+fn compile_bytecode(src: &str, heap: &mut Heap) -> Result<ObjRef, LoxError> {
+    let (stmts, _) = interpreter::compile(src).map_err(LoxError::Compile)?;
+    compiler::compile(&stmts, heap).map_err(LoxError::Compile)
+}
+
+fn dump_bytecode(src: &str) -> Result<(), LoxError> {
+    let mut heap = Heap::new();
+    let script = compile_bytecode(src, &mut heap)?;
+
+    print!("{}", disassemble(script, &heap));
+    Ok(())
+}
+
+// Compile a script and write its bytecode to a file, to be run later without recompiling
+fn compile(src: &str, output: &str) -> Result<(), LoxError> {
+    let mut heap = Heap::new();
+    let script = compile_bytecode(src, &mut heap)?;
+
+    if let Err(e) = fs::write(output, loxc::write(script, &heap)) {
+        eprintln!("Failed to write output file {}: {}", output, e);
+        process::exit(EX_CANTCREAT);
+    }
+    Ok(())
 }
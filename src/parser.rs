@@ -1,32 +1,157 @@
-use crate::lexer::{LitType, OpType, Token, TokenType, KwordType};
+use crate::lexer::{LitType, OpType, Span, Token, TokenType, KwordType};
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-#[derive(Debug)]
+// Identifies an expression that the resolver binds to a scope
+pub type ExprId = usize;
+
+// Shared by all parsers so that ids stay unique across separately parsed sources
+static NEXT_EXPR_ID: AtomicUsize = AtomicUsize::new(0);
+
+// The most arguments a call, or parameters a function, may have
+const MAX_ARGS: usize = 255;
+
+#[derive(Debug, Clone)]
 pub enum Expr {
-    // Binary expression
-    Bin(Box<Expr>, OpType, Box<Expr>),
+    // Assignment expression
+    Assign(Token, Box<Expr>, ExprId),
+    // Binary expression; the token is the operator
+    Bin(Box<Expr>, Token, Box<Expr>),
+    // Call expression; the token is the closing parenthesis
+    Call(Box<Expr>, Token, Vec<Expr>),
+    // Property access
+    Get(Box<Expr>, Token),
     // Grouping expression
     Grouping(Box<Expr>),
-    // Literal expression
-    Lit(LitType),
-    // Unary expression
-    Unary(OpType, Box<Expr>),
+    // Literal expression, and where it was written
+    Lit(LitType, Span),
+    // Logical expression; 'and' or 'or'
+    Logical(Box<Expr>, KwordType, Box<Expr>),
+    // Property assignment
+    Set(Box<Expr>, Token, Box<Expr>),
+    // Superclass method access; the 'super' keyword and the method name
+    Super(Token, Token, ExprId),
+    // The instance a method is bound to
+    This(Token, ExprId),
+    // Unary expression; the token is the operator
+    Unary(Token, Box<Expr>),
+    // Variable expression
+    Variable(Token, ExprId),
+}
+
+#[derive(Debug, Clone)]
+pub enum Stmt {
+    // Block statement
+    Block(Vec<Stmt>),
+    // Class declaration
+    Class(ClassDecl),
+    // Expression statement
+    Expression(Expr),
+    // Function declaration
+    Function(Rc<FunctionDecl>),
+    // If statement with an optional else branch
+    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
+    // Print statement
+    Print(Expr),
+    // Return statement with an optional value; the token is the 'return' keyword
+    Return(Token, Option<Expr>),
+    // Variable declaration with an optional initializer
+    Var(Token, Option<Expr>),
+    // While loop; 'for' loops are desugared into these
+    While(Expr, Box<Stmt>),
+}
+
+// A named function: its parameters and body
+#[derive(Debug, Clone)]
+pub struct FunctionDecl {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<Stmt>,
+}
+
+// A class: its optional superclass, which is always a variable expression, and its methods
+#[derive(Debug, Clone)]
+pub struct ClassDecl {
+    pub name: Token,
+    pub superclass: Option<Expr>,
+    pub methods: Vec<Rc<FunctionDecl>>,
 }
 
 pub trait Visitor<T> {
     fn visit_expr(&mut self, expr: &Expr) -> T;
 }
 
+pub trait StmtVisitor<T> {
+    fn visit_stmt(&mut self, stmt: &Stmt) -> T;
+}
+
+// A syntax error along with the token where it was found
+#[derive(Debug)]
+pub struct ParseError {
+    pub token: Token,
+    // What the parser expected to find instead
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
 pub struct Parser {
     tokens: Vec<Token>,
     curr: usize,
+    errors: Vec<ParseError>,
 }
 
 impl Parser {
-    fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, curr: 0 }
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Parser {
+            tokens,
+            curr: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    // Parse the tokens as a program: a list of declarations followed by EOF.
+    // Parsing carries on past syntax errors so that all of them are reported at once.
+    pub fn parse_tokens(&mut self) -> Result<Vec<Stmt>, Vec<ParseError>> {
+        let mut stmts = Vec::new();
+
+        while !self.is_at_end() {
+            if let Some(stmt) = self.declaration() {
+                stmts.push(stmt);
+            }
+        }
+
+        if self.errors.is_empty() {
+            Ok(stmts)
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
     }
 
-    pub fn parse_tokens(&mut self) {}
+    // Parse the tokens as a lone expression followed by EOF, as typed into the REPL
+    pub fn parse_expression(&mut self) -> Result<Expr, Vec<ParseError>> {
+        let result = self.expression().and_then(|expr| {
+            if self.is_at_end() {
+                Ok(expr)
+            } else {
+                Err(self.error(self.peek(), "Expect end of expression."))
+            }
+        });
+
+        match result {
+            Ok(expr) if self.errors.is_empty() => Ok(expr),
+            Ok(_) => Err(std::mem::take(&mut self.errors)),
+            Err(err) => {
+                self.errors.push(err);
+                Err(std::mem::take(&mut self.errors))
+            }
+        }
+    }
 
     fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
@@ -58,6 +183,48 @@ impl Parser {
         self.peek().token_type == *tok_type
     }
 
+    // Consume the current token if it is of the given type, otherwise fail with the given message
+    fn expect(&mut self, tok_type: TokenType, err_msg: &str) -> Result<&Token, ParseError> {
+        if !self.check(&tok_type) {
+            return Err(self.error(self.peek(), err_msg));
+        }
+
+        Ok(self.advance())
+    }
+
+    // Build an error at the given token
+    fn error(&self, token: &Token, message: &str) -> ParseError {
+        ParseError {
+            token: token.clone(),
+            message: message.to_string(),
+        }
+    }
+
+    // Skip tokens until the start of what is probably the next statement
+    fn synchronize(&mut self) {
+        self.advance();
+
+        while !self.is_at_end() {
+            if self.prev().token_type == TokenType::Op(OpType::Semicolon) {
+                return;
+            }
+
+            match self.peek().token_type {
+                TokenType::Kword(KwordType::Class)
+                | TokenType::Kword(KwordType::Fun)
+                | TokenType::Kword(KwordType::Var)
+                | TokenType::Kword(KwordType::For)
+                | TokenType::Kword(KwordType::If)
+                | TokenType::Kword(KwordType::While)
+                | TokenType::Kword(KwordType::Print)
+                | TokenType::Kword(KwordType::Return) => return,
+                _ => {
+                    self.advance();
+                }
+            }
+        }
+    }
+
     fn peek(&self) -> &Token {
         self.tokens.get(self.curr).expect("Missing token")
     }
@@ -66,34 +233,341 @@ impl Parser {
         self.curr >= self.tokens.len() || self.peek().token_type == TokenType::Eof
     }
 
-    fn prev(&mut self) -> &Token {
+    fn prev(&self) -> &Token {
         self.tokens.get(self.curr - 1).expect("Missing token")
     }
 
+    // Parse a declaration, recording the error and skipping to the next statement if it fails
+    fn declaration(&mut self) -> Option<Stmt> {
+        match self.try_declaration() {
+            Ok(stmt) => Some(stmt),
+            Err(err) => {
+                self.errors.push(err);
+                self.synchronize();
+                None
+            }
+        }
+    }
+
+    fn try_declaration(&mut self) -> Result<Stmt, ParseError> {
+        if self.consume(&[TokenType::Kword(KwordType::Class)]) {
+            return self.class_declaration();
+        }
+
+        if self.consume(&[TokenType::Kword(KwordType::Fun)]) {
+            return Ok(Stmt::Function(Rc::new(self.function("function")?)));
+        }
+
+        if self.consume(&[TokenType::Kword(KwordType::Var)]) {
+            return self.var_declaration();
+        }
+
+        self.statement()
+    }
+
+    // Parse the rest of a class declaration after 'class'
+    fn class_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let name = self
+            .expect(
+                TokenType::Lit(LitType::Identifier("".to_string())),
+                "Expect class name.",
+            )?
+            .clone();
+
+        let superclass = if self.consume(&[TokenType::Op(OpType::Less)]) {
+            let superclass_name = self
+                .expect(
+                    TokenType::Lit(LitType::Identifier("".to_string())),
+                    "Expect superclass name.",
+                )?
+                .clone();
+            Some(Expr::Variable(superclass_name, next_expr_id()))
+        } else {
+            None
+        };
+
+        self.expect(TokenType::Op(OpType::LeftBrace), "Expect '{' before class body.")?;
+
+        let mut methods = Vec::new();
+        while !self.check(&TokenType::Op(OpType::RightBrace)) && !self.is_at_end() {
+            methods.push(Rc::new(self.function("method")?));
+        }
+
+        self.expect(TokenType::Op(OpType::RightBrace), "Expect '}' after class body.")?;
+
+        Ok(Stmt::Class(ClassDecl {
+            name,
+            superclass,
+            methods,
+        }))
+    }
+
+    // Parse a function's name, parameters and body
+    fn function(&mut self, kind: &str) -> Result<FunctionDecl, ParseError> {
+        let name = self
+            .expect(
+                TokenType::Lit(LitType::Identifier("".to_string())),
+                &format!("Expect {} name.", kind),
+            )?
+            .clone();
+        self.expect(
+            TokenType::Op(OpType::LeftParen),
+            &format!("Expect '(' after {} name.", kind),
+        )?;
+
+        let mut params = Vec::new();
+        if !self.check(&TokenType::Op(OpType::RightParen)) {
+            loop {
+                // Too many parameters is reported without abandoning the declaration
+                if params.len() >= MAX_ARGS {
+                    let err = self.error(
+                        self.peek(),
+                        &format!("Can't have more than {} parameters.", MAX_ARGS),
+                    );
+                    self.errors.push(err);
+                }
+
+                params.push(
+                    self.expect(
+                        TokenType::Lit(LitType::Identifier("".to_string())),
+                        "Expect parameter name.",
+                    )?
+                    .clone(),
+                );
+
+                if !self.consume(&[TokenType::Op(OpType::Comma)]) {
+                    break;
+                }
+            }
+        }
+        self.expect(TokenType::Op(OpType::RightParen), "Expect ')' after parameters.")?;
+
+        self.expect(
+            TokenType::Op(OpType::LeftBrace),
+            &format!("Expect '{{' before {} body.", kind),
+        )?;
+        let body = self.block()?;
+
+        Ok(FunctionDecl { name, params, body })
+    }
+
+    // Parse the rest of a variable declaration after 'var'
+    fn var_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let name = self
+            .expect(
+                TokenType::Lit(LitType::Identifier("".to_string())),
+                "Expect variable name.",
+            )?
+            .clone();
+
+        let initializer = if self.consume(&[TokenType::Op(OpType::Equal)]) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+
+        self.expect(
+            TokenType::Op(OpType::Semicolon),
+            "Expect ';' after variable declaration.",
+        )?;
+
+        Ok(Stmt::Var(name, initializer))
+    }
+
+    // Parse a statement
+    fn statement(&mut self) -> Result<Stmt, ParseError> {
+        if self.consume(&[TokenType::Kword(KwordType::For)]) {
+            return self.for_statement();
+        }
+
+        if self.consume(&[TokenType::Kword(KwordType::If)]) {
+            return self.if_statement();
+        }
+
+        if self.consume(&[TokenType::Kword(KwordType::While)]) {
+            return self.while_statement();
+        }
+
+        if self.consume(&[TokenType::Kword(KwordType::Print)]) {
+            let value = self.expression()?;
+            self.expect(TokenType::Op(OpType::Semicolon), "Expect ';' after value.")?;
+            return Ok(Stmt::Print(value));
+        }
+
+        if self.consume(&[TokenType::Kword(KwordType::Return)]) {
+            let keyword = self.prev().clone();
+            let value = if self.check(&TokenType::Op(OpType::Semicolon)) {
+                None
+            } else {
+                Some(self.expression()?)
+            };
+            self.expect(TokenType::Op(OpType::Semicolon), "Expect ';' after return value.")?;
+            return Ok(Stmt::Return(keyword, value));
+        }
+
+        if self.consume(&[TokenType::Op(OpType::LeftBrace)]) {
+            return Ok(Stmt::Block(self.block()?));
+        }
+
+        let expr = self.expression()?;
+        self.expect(TokenType::Op(OpType::Semicolon), "Expect ';' after expression.")?;
+        Ok(Stmt::Expression(expr))
+    }
+
+    // Parse the rest of a 'for' loop, desugaring it into a while loop:
+    // { initializer; while (condition) { body; increment; } }
+    fn for_statement(&mut self) -> Result<Stmt, ParseError> {
+        self.expect(TokenType::Op(OpType::LeftParen), "Expect '(' after 'for'.")?;
+
+        let initializer = if self.consume(&[TokenType::Op(OpType::Semicolon)]) {
+            None
+        } else if self.consume(&[TokenType::Kword(KwordType::Var)]) {
+            Some(self.var_declaration()?)
+        } else {
+            let expr = self.expression()?;
+            self.expect(TokenType::Op(OpType::Semicolon), "Expect ';' after loop initializer.")?;
+            Some(Stmt::Expression(expr))
+        };
+
+        let condition = if self.check(&TokenType::Op(OpType::Semicolon)) {
+            Expr::Lit(LitType::True, self.peek().span)
+        } else {
+            self.expression()?
+        };
+        self.expect(TokenType::Op(OpType::Semicolon), "Expect ';' after loop condition.")?;
+
+        let increment = if self.check(&TokenType::Op(OpType::RightParen)) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.expect(TokenType::Op(OpType::RightParen), "Expect ')' after for clauses.")?;
+
+        let mut body = self.statement()?;
+
+        if let Some(increment) = increment {
+            body = Stmt::Block(vec![body, Stmt::Expression(increment)]);
+        }
+
+        body = Stmt::While(condition, Box::new(body));
+
+        if let Some(initializer) = initializer {
+            body = Stmt::Block(vec![initializer, body]);
+        }
+
+        Ok(body)
+    }
+
+    // Parse the rest of an 'if' statement; an 'else' binds to the nearest 'if'
+    fn if_statement(&mut self) -> Result<Stmt, ParseError> {
+        self.expect(TokenType::Op(OpType::LeftParen), "Expect '(' after 'if'.")?;
+        let condition = self.expression()?;
+        self.expect(TokenType::Op(OpType::RightParen), "Expect ')' after if condition.")?;
+
+        let then_branch = Box::new(self.statement()?);
+        let else_branch = if self.consume(&[TokenType::Kword(KwordType::Else)]) {
+            Some(Box::new(self.statement()?))
+        } else {
+            None
+        };
+
+        Ok(Stmt::If(condition, then_branch, else_branch))
+    }
+
+    // Parse the rest of a 'while' loop
+    fn while_statement(&mut self) -> Result<Stmt, ParseError> {
+        self.expect(TokenType::Op(OpType::LeftParen), "Expect '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.expect(TokenType::Op(OpType::RightParen), "Expect ')' after condition.")?;
+
+        Ok(Stmt::While(condition, Box::new(self.statement()?)))
+    }
+
+    // Parse the declarations of a block after '{'
+    fn block(&mut self) -> Result<Vec<Stmt>, ParseError> {
+        let mut stmts = Vec::new();
+
+        while !self.check(&TokenType::Op(OpType::RightBrace)) && !self.is_at_end() {
+            if let Some(stmt) = self.declaration() {
+                stmts.push(stmt);
+            }
+        }
+
+        self.expect(TokenType::Op(OpType::RightBrace), "Expect '}' after block.")?;
+        Ok(stmts)
+    }
+
     // Parse an expression
-    fn expression(&mut self) -> Expr {
-        self.equality()
+    fn expression(&mut self) -> Result<Expr, ParseError> {
+        self.assignment()
+    }
+
+    // Parse an assignment, which is right-associative
+    fn assignment(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.or()?;
+
+        if self.consume(&[TokenType::Op(OpType::Equal)]) {
+            let equals = self.prev().clone();
+            let value = self.assignment()?;
+
+            return match expr {
+                Expr::Variable(name, _) => Ok(Expr::Assign(name, Box::new(value), next_expr_id())),
+                Expr::Get(object, name) => Ok(Expr::Set(object, name, Box::new(value))),
+                _ => {
+                    // The parser isn't confused, so report the error without synchronizing
+                    let err = self.error(&equals, "Invalid assignment target.");
+                    self.errors.push(err);
+                    Ok(expr)
+                }
+            };
+        }
+
+        Ok(expr)
+    }
+
+    // Parse a logical 'or'
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.and()?;
+
+        while self.consume(&[TokenType::Kword(KwordType::Or)]) {
+            let rh = self.and()?;
+            expr = Expr::Logical(Box::new(expr), KwordType::Or, Box::new(rh));
+        }
+
+        Ok(expr)
+    }
+
+    // Parse a logical 'and'
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.equality()?;
+
+        while self.consume(&[TokenType::Kword(KwordType::And)]) {
+            let rh = self.equality()?;
+            expr = Expr::Logical(Box::new(expr), KwordType::And, Box::new(rh));
+        }
+
+        Ok(expr)
     }
 
     // Parse an equality
-    fn equality(&mut self) -> Expr {
+    fn equality(&mut self) -> Result<Expr, ParseError> {
         // Parse the LH comparator
-        let mut expr = self.comparison();
+        let mut expr = self.comparison()?;
 
         while self.consume(&[
             TokenType::Op(OpType::EqualEqual),
             TokenType::Op(OpType::BangEqual),
         ]) {
-            let op = self.prev().op_type().expect("Expected operator").clone();
-            let rh = self.comparison();
+            let op = self.prev().clone();
+            let rh = self.comparison()?;
             expr = Expr::Bin(Box::new(expr), op, Box::new(rh));
         }
 
-        expr
+        Ok(expr)
     }
 
-    fn comparison(&mut self) -> Expr {
-        let mut expr = self.term();
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.term()?;
 
         while self.consume(&[
             TokenType::Op(OpType::Greater),
@@ -101,156 +575,357 @@ impl Parser {
             TokenType::Op(OpType::Less),
             TokenType::Op(OpType::LessEqual),
         ]) {
-            let op = self.prev().op_type().expect("Expected operator").clone();
-            let rh = self.term();
+            let op = self.prev().clone();
+            let rh = self.term()?;
             expr = Expr::Bin(Box::new(expr), op, Box::new(rh));
         }
 
-        expr
+        Ok(expr)
     }
 
     // Parse a term
-    fn term(&mut self) -> Expr {
-        let mut expr = self.factor();
+    fn term(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.factor()?;
 
         while self.consume(&[
             TokenType::Op(OpType::Plus),
             TokenType::Op(OpType::Minus),
         ]) {
-            let op = self.prev().op_type().expect("Expected operator").clone();
-            let rh = self.factor();
+            let op = self.prev().clone();
+            let rh = self.factor()?;
             expr = Expr::Bin(Box::new(expr), op, Box::new(rh));
         }
 
-        expr
+        Ok(expr)
     }
 
     // Parse a factor
-    fn factor(&mut self) -> Expr {
-        let mut expr = self.unary();
+    fn factor(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.unary()?;
 
         while self.consume(&[
             TokenType::Op(OpType::Slash),
             TokenType::Op(OpType::Star),
         ]) {
-            let op = self.prev().op_type().expect("Expected operator").clone();
-            let rh = self.unary();
+            let op = self.prev().clone();
+            let rh = self.unary()?;
             expr = Expr::Bin(Box::new(expr), op, Box::new(rh));
         }
 
-        expr
+        Ok(expr)
     }
 
     // Parse a unary rule
-    fn unary(&mut self) -> Expr {
+    fn unary(&mut self) -> Result<Expr, ParseError> {
         if self.consume(&[
             TokenType::Op(OpType::Bang),
             TokenType::Op(OpType::Minus),
         ]) {
-            let op = self.prev().op_type().expect("Expected operator").clone();
-            return Expr::Unary(op.clone(), Box::new(self.unary()));
+            let op = self.prev().clone();
+            return Ok(Expr::Unary(op, Box::new(self.unary()?)));
         }
 
-        self.primary()
+        self.call()
     }
 
-    fn primary(&mut self) -> Expr {
+    // Parse a primary expression followed by any number of calls and property accesses
+    fn call(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.consume(&[TokenType::Op(OpType::LeftParen)]) {
+                expr = self.finish_call(expr)?;
+            } else if self.consume(&[TokenType::Op(OpType::Dot)]) {
+                let name = self
+                    .expect(
+                        TokenType::Lit(LitType::Identifier("".to_string())),
+                        "Expect property name after '.'.",
+                    )?
+                    .clone();
+                expr = Expr::Get(Box::new(expr), name);
+            } else {
+                break;
+            }
+        }
+
+        Ok(expr)
+    }
+
+    // Parse the arguments of a call after '('
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParseError> {
+        let mut args = Vec::new();
+
+        if !self.check(&TokenType::Op(OpType::RightParen)) {
+            loop {
+                // Too many arguments is reported without abandoning the call
+                if args.len() >= MAX_ARGS {
+                    let err = self.error(
+                        self.peek(),
+                        &format!("Can't have more than {} arguments.", MAX_ARGS),
+                    );
+                    self.errors.push(err);
+                }
+
+                args.push(self.expression()?);
+
+                if !self.consume(&[TokenType::Op(OpType::Comma)]) {
+                    break;
+                }
+            }
+        }
+
+        let paren = self
+            .expect(TokenType::Op(OpType::RightParen), "Expect ')' after arguments.")?
+            .clone();
+
+        Ok(Expr::Call(Box::new(callee), paren, args))
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
         // false
         if self.consume(&[
             TokenType::Kword(KwordType::False),
         ]) {
-            return Expr::Lit(LitType::False);
+            return Ok(Expr::Lit(LitType::False, self.prev().span));
         }
         // true
         if self.consume(&[
             TokenType::Kword(KwordType::True),
         ]) {
-            return Expr::Lit(LitType::True);
+            return Ok(Expr::Lit(LitType::True, self.prev().span));
         }
         // nil
         if self.consume(&[
             TokenType::Kword(KwordType::Nil),
         ]) {
-            return Expr::Lit(LitType::Nil);
+            return Ok(Expr::Lit(LitType::Nil, self.prev().span));
         }
         // string or number
         if self.consume(&[
             TokenType::Lit(LitType::String("".to_string())),
             TokenType::Lit(LitType::Number(0.0)),
         ]) {
-            let str_val = self.prev().str_val().expect("Expected string").to_owned();
-
-            return Expr::Lit(LitType::String(str_val));
+            if let TokenType::Lit(ref lit_type) = self.prev().token_type {
+                return Ok(Expr::Lit(lit_type.clone(), self.prev().span));
+            }
+        }
+        // this
+        if self.consume(&[TokenType::Kword(KwordType::This)]) {
+            return Ok(Expr::This(self.prev().clone(), next_expr_id()));
+        }
+        // super
+        if self.consume(&[TokenType::Kword(KwordType::Super)]) {
+            let keyword = self.prev().clone();
+            self.expect(TokenType::Op(OpType::Dot), "Expect '.' after 'super'.")?;
+            let method = self
+                .expect(
+                    TokenType::Lit(LitType::Identifier("".to_string())),
+                    "Expect superclass method name.",
+                )?
+                .clone();
+            return Ok(Expr::Super(keyword, method, next_expr_id()));
+        }
+        // identifier
+        if self.consume(&[TokenType::Lit(LitType::Identifier("".to_string()))]) {
+            return Ok(Expr::Variable(self.prev().clone(), next_expr_id()));
         }
 
         if !self.consume(&[
             TokenType::Op(OpType::LeftParen)
         ]) {
-            panic!("No parse match in primary()");
+            return Err(self.error(self.peek(), "Expect expression."));
         }
 
-        let expr = self.expression();
-        if !self.consume(&[TokenType::Op(OpType::RightParen)]) {
-            panic!("Missing ')' in primary()");
-        }
+        let expr = self.expression()?;
+        self.expect(TokenType::Op(OpType::RightParen), "Expect ')' after expression.")?;
 
-        Expr::Grouping(Box::new(expr))
+        Ok(Expr::Grouping(Box::new(expr)))
     }
 }
 
-impl Visitor<()> for Parser {
-    fn visit_expr(&mut self, expr: &Expr) {
-        match *expr {
-            Expr::Bin(ref left, ref op, ref right) => {
-                self.visit_expr(left);
-                print!(" {} ", op.to_string());
-                self.visit_expr(right);
-            }
-            Expr::Grouping(ref expr) => {
-                print!("(");
-                self.visit_expr(expr);
-                print!(")");
-            }
-            Expr::Lit(ref lit_type) => {
-                print!("{}", lit_type.to_string());
-            }
-            Expr::Unary(ref op, ref expr) => {
-                print!("{}", op.to_string());
-                self.visit_expr(expr);
-            }
-        };
-    }
+// Allocate a fresh expression id
+fn next_expr_id() -> ExprId {
+    NEXT_EXPR_ID.fetch_add(1, Ordering::Relaxed)
 }
 
 #[cfg(test)]
 mod test_parser {
     use super::*;
+    use crate::lexer::Lexer;
+
+    // Lex a source file into a token stream
+    fn lex_src(src: &str) -> Vec<Token> {
+        Lexer::new(src)
+            .lex_tokens()
+            .into_iter()
+            .map(|tok| tok.unwrap())
+            .collect()
+    }
 
     #[test]
-    fn test_visit() {
-        let expressions = vec![
-            // 1.5 + 1.5
-            Expr::Bin(
-                Box::new(Expr::Lit(LitType::Number(1.5))),
-                OpType::Plus,
-                Box::new(Expr::Lit(LitType::Number(1.5))),
-            ),
-            // (5 * 4)
-            Expr::Grouping(Box::new(Expr::Bin(
-                Box::new(Expr::Lit(LitType::Number(5.0))),
-                OpType::Star,
-                Box::new(Expr::Lit(LitType::Number(4.0))),
-            ))),
-            // "hello"
-            Expr::Lit(LitType::String("hello".to_string())),
-            // -1
-            Expr::Unary(OpType::Minus, Box::new(Expr::Lit(LitType::Number(1.0)))),
-        ];
-
-        let mut parser = Parser::new(Vec::new());
-        for expr in expressions {
-            parser.visit_expr(&expr);
-            println!();
+    fn test_parse_files() {
+        let stmts = Parser::new(lex_src(include_str!("../tests/data/1_hello.lox"))).parse_tokens().unwrap();
+        assert_eq!(18, stmts.len());
+        assert!(matches!(stmts[0], Stmt::Print(_)));
+        assert!(matches!(
+            stmts[14],
+            Stmt::Expression(Expr::Logical(_, KwordType::And, _))
+        ));
+
+        let stmts =
+            Parser::new(lex_src(include_str!("../tests/data/2_scopes_and_vars.lox"))).parse_tokens().unwrap();
+        assert_eq!(7, stmts.len());
+        assert!(matches!(stmts[0], Stmt::Block(ref block) if block.len() == 2));
+        assert!(matches!(stmts[2], Stmt::Var(_, None)));
+        assert!(matches!(stmts[5], Stmt::Expression(Expr::Assign(_, _, _))));
+    }
+
+    #[test]
+    fn test_precedence() {
+        // Assignment is right-associative and binds looser than 'or', which binds looser than 'and'
+        let stmts = Parser::new(lex_src("a = b = c or d and e;")).parse_tokens().unwrap();
+        match &stmts[0] {
+            Stmt::Expression(Expr::Assign(_, value, _)) => match value.as_ref() {
+                Expr::Assign(_, value, _) => {
+                    assert!(matches!(
+                        value.as_ref(),
+                        Expr::Logical(_, KwordType::Or, right)
+                            if matches!(right.as_ref(), Expr::Logical(_, KwordType::And, _))
+                    ));
+                }
+                other => panic!("Expected nested assignment, got {:?}", other),
+            },
+            other => panic!("Expected assignment, got {:?}", other),
         }
     }
+
+    #[test]
+    fn test_parse_control_flow() {
+        let stmts =
+            Parser::new(lex_src(include_str!("../tests/data/3_control_flow.lox"))).parse_tokens().unwrap();
+        assert_eq!(4, stmts.len());
+        assert!(matches!(stmts[1], Stmt::If(_, _, Some(_))));
+
+        // for (var a = 1; a < 10; a = a + 1) body  =>  { var a = 1; while (a < 10) { body; a = a + 1; } }
+        match &stmts[3] {
+            Stmt::Block(outer) => {
+                assert!(matches!(outer[0], Stmt::Var(_, Some(_))));
+                match &outer[1] {
+                    Stmt::While(Expr::Bin(_, ref op, _), body) if op.op_type() == Some(&OpType::Less) => {
+                        assert!(matches!(
+                            body.as_ref(),
+                            Stmt::Block(inner) if matches!(inner[1], Stmt::Expression(Expr::Assign(_, _, _)))
+                        ));
+                    }
+                    other => panic!("Expected while loop, got {:?}", other),
+                }
+            }
+            other => panic!("Expected desugared for loop, got {:?}", other),
+        }
+
+        // A for loop without clauses loops forever on its body alone
+        let stmts = Parser::new(lex_src("for (;;) print 1;")).parse_tokens().unwrap();
+        assert!(matches!(
+            stmts[0],
+            Stmt::While(Expr::Lit(LitType::True, _), ref body) if matches!(body.as_ref(), Stmt::Print(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_functions() {
+        let stmts =
+            Parser::new(lex_src(include_str!("../tests/data/4_functions.lox"))).parse_tokens().unwrap();
+        assert_eq!(9, stmts.len());
+
+        match &stmts[0] {
+            Stmt::Function(decl) => {
+                assert_eq!(Some("printSum".to_string()), decl.name.str_val());
+                assert_eq!(2, decl.params.len());
+                assert_eq!(1, decl.body.len());
+            }
+            other => panic!("Expected function declaration, got {:?}", other),
+        }
+        assert!(matches!(
+            stmts[2],
+            Stmt::Var(_, Some(Expr::Call(_, _, ref args))) if args.len() == 2
+        ));
+
+        // Calls chain left to right: f(1)(2)()
+        let stmts = Parser::new(lex_src("f(1)(2)();")).parse_tokens().unwrap();
+        match &stmts[0] {
+            Stmt::Expression(Expr::Call(callee, _, args)) => {
+                assert!(args.is_empty());
+                assert!(matches!(callee.as_ref(), Expr::Call(_, _, _)));
+            }
+            other => panic!("Expected call, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_classes() {
+        let stmts = Parser::new(lex_src(
+            "class Brunch < Breakfast { init(drink) { super.init(); this.drink = drink; } drink() {} } \
+             brunch.drink.cup = 1;",
+        ))
+        .parse_tokens()
+        .unwrap();
+
+        match &stmts[0] {
+            Stmt::Class(decl) => {
+                assert_eq!(Some("Brunch".to_string()), decl.name.str_val());
+                assert!(matches!(decl.superclass, Some(Expr::Variable(_, _))));
+                assert_eq!(2, decl.methods.len());
+                assert!(matches!(
+                    decl.methods[0].body[0],
+                    Stmt::Expression(Expr::Call(ref callee, _, _)) if matches!(callee.as_ref(), Expr::Super(_, _, _))
+                ));
+                assert!(matches!(
+                    decl.methods[0].body[1],
+                    Stmt::Expression(Expr::Set(ref object, _, _)) if matches!(object.as_ref(), Expr::This(_, _))
+                ));
+            }
+            other => panic!("Expected class declaration, got {:?}", other),
+        }
+
+        // Only the last property in a chain is assigned to
+        assert!(matches!(
+            stmts[1],
+            Stmt::Expression(Expr::Set(ref object, _, _)) if matches!(object.as_ref(), Expr::Get(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_assignment_target() {
+        let errs = Parser::new(lex_src("1 + a = 3;")).parse_tokens().unwrap_err();
+
+        assert_eq!(1, errs.len());
+        assert_eq!("Invalid assignment target.", errs[0].message);
+        assert_eq!("=", errs[0].token.lexeme);
+    }
+
+    #[test]
+    fn test_error_recovery() {
+        let src = "print (1;\nvar = 2;\nvar b = 1 2;\nprint 3 +;\nprint 4;";
+        let errs = Parser::new(lex_src(src)).parse_tokens().unwrap_err();
+
+        // Every broken statement is reported, each at the token where parsing failed
+        let found: Vec<(usize, &str, &str)> = errs
+            .iter()
+            .map(|e| (e.token.span.line, e.token.lexeme.as_str(), e.message.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (1, ";", "Expect ')' after expression."),
+                (2, "=", "Expect variable name."),
+                (3, "2", "Expect ';' after variable declaration."),
+                (4, ";", "Expect expression."),
+            ],
+            found
+        );
+
+        // Errors at the end of the input point at EOF
+        let errs = Parser::new(lex_src("{ print 1;")).parse_tokens().unwrap_err();
+        assert_eq!(TokenType::Eof, errs[0].token.token_type);
+        assert_eq!("Expect '}' after block.", errs[0].message);
+    }
 }
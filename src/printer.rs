@@ -0,0 +1,884 @@
+use crate::lexer::{KwordType, LitType, OpType, Token};
+use crate::parser::{Expr, FunctionDecl, Stmt, StmtVisitor, Visitor};
+use std::slice;
+
+// List tokens one per line with where they start, as in "1:5 Lit(Identifier("a")) "a""
+pub fn print_tokens(tokens: &[Token]) -> String {
+    tokens
+        .iter()
+        .map(|token| {
+            format!(
+                "{}:{} {:?} {:?}\n",
+                token.span.line, token.span.column, token.token_type, token.lexeme
+            )
+        })
+        .collect()
+}
+
+// Prints the AST as S-expressions, one statement per line with nested statements indented
+#[derive(Default)]
+pub struct AstPrinter {
+    depth: usize,
+}
+
+impl AstPrinter {
+    pub fn new() -> Self {
+        AstPrinter::default()
+    }
+
+    pub fn print(&mut self, stmts: &[Stmt]) -> String {
+        stmts
+            .iter()
+            .map(|stmt| self.visit_stmt(stmt) + "\n")
+            .collect()
+    }
+
+    // Print a parenthesized list whose elements are expressions
+    fn parenthesize(&mut self, name: &str, exprs: &[&Expr]) -> String {
+        let mut out = format!("({}", name);
+        for expr in exprs {
+            out.push(' ');
+            out.push_str(&self.visit_expr(expr));
+        }
+        out.push(')');
+        out
+    }
+
+    // Print a parenthesized list whose remaining elements, rendered one level deeper, go on
+    // lines of their own
+    fn nest(&mut self, head: String, render: impl FnOnce(&mut Self) -> Vec<String>) -> String {
+        self.depth += 1;
+        let indent = "  ".repeat(self.depth);
+        let lines = render(self);
+        self.depth -= 1;
+
+        let mut out = format!("({}", head);
+        for line in lines {
+            out.push('\n');
+            out.push_str(&indent);
+            out.push_str(&line);
+        }
+        out.push(')');
+        out
+    }
+
+    fn stmts(&mut self, stmts: &[&Stmt]) -> Vec<String> {
+        stmts.iter().map(|stmt| self.visit_stmt(stmt)).collect()
+    }
+
+    fn function(&mut self, decl: &FunctionDecl) -> String {
+        let params: Vec<&str> = decl
+            .params
+            .iter()
+            .map(|param| param.lexeme.as_str())
+            .collect();
+        let head = format!("fun {} ({})", decl.name.lexeme, params.join(" "));
+        self.nest(head, |printer| {
+            decl.body
+                .iter()
+                .map(|stmt| printer.visit_stmt(stmt))
+                .collect()
+        })
+    }
+}
+
+impl Visitor<String> for AstPrinter {
+    fn visit_expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Assign(name, value, _) => {
+                self.parenthesize(&format!("= {}", name.lexeme), &[value])
+            }
+            Expr::Bin(left, op, right) => self.parenthesize(&op.lexeme, &[left, right]),
+            Expr::Call(callee, _, args) => {
+                let mut exprs = vec![callee.as_ref()];
+                exprs.extend(args);
+                self.parenthesize("call", &exprs)
+            }
+            Expr::Get(object, name) => format!("(. {} {})", self.visit_expr(object), name.lexeme),
+            Expr::Grouping(expr) => self.parenthesize("group", &[expr]),
+            Expr::Lit(LitType::String(s), _) => format!("{:?}", s),
+            Expr::Lit(lit, _) => lit.to_string(),
+            Expr::Logical(left, kword, right) => {
+                self.parenthesize(&kword.to_string(), &[left, right])
+            }
+            Expr::Set(object, name, value) => format!(
+                "(set {} {} {})",
+                self.visit_expr(object),
+                name.lexeme,
+                self.visit_expr(value)
+            ),
+            Expr::Super(_, method, _) => format!("(super {})", method.lexeme),
+            Expr::This(_, _) => "this".to_string(),
+            Expr::Unary(op, operand) => self.parenthesize(&op.lexeme, &[operand]),
+            Expr::Variable(name, _) => name.lexeme.clone(),
+        }
+    }
+}
+
+impl StmtVisitor<String> for AstPrinter {
+    fn visit_stmt(&mut self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::Block(stmts) => self.nest("block".to_string(), |printer| {
+                stmts.iter().map(|stmt| printer.visit_stmt(stmt)).collect()
+            }),
+            Stmt::Class(decl) => {
+                let mut head = format!("class {}", decl.name.lexeme);
+                if let Some(superclass) = &decl.superclass {
+                    head.push_str(" < ");
+                    head.push_str(&self.visit_expr(superclass));
+                }
+
+                self.nest(head, |printer| {
+                    decl.methods
+                        .iter()
+                        .map(|method| printer.function(method))
+                        .collect()
+                })
+            }
+            Stmt::Expression(expr) => self.parenthesize("expr", &[expr]),
+            Stmt::Function(decl) => self.function(decl),
+            Stmt::If(condition, then_branch, else_branch) => {
+                let head = format!("if {}", self.visit_expr(condition));
+                match else_branch {
+                    Some(else_branch) => {
+                        self.nest(head, |printer| printer.stmts(&[then_branch, else_branch]))
+                    }
+                    None => self.nest(head, |printer| printer.stmts(&[then_branch])),
+                }
+            }
+            Stmt::Print(expr) => self.parenthesize("print", &[expr]),
+            Stmt::Return(_, Some(value)) => self.parenthesize("return", &[value]),
+            Stmt::Return(_, None) => "(return)".to_string(),
+            Stmt::Var(name, Some(initializer)) => {
+                self.parenthesize(&format!("var {}", name.lexeme), &[initializer])
+            }
+            Stmt::Var(name, None) => format!("(var {})", name.lexeme),
+            Stmt::While(condition, body) => {
+                let head = format!("while {}", self.visit_expr(condition));
+                self.nest(head, |printer| printer.stmts(&[body]))
+            }
+        }
+    }
+}
+
+// How tightly an expression binds, from assignment up to primary expressions
+const ASSIGNMENT: u8 = 1;
+const UNARY: u8 = 8;
+const CALL: u8 = 9;
+
+fn precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Assign(..) | Expr::Set(..) => ASSIGNMENT,
+        Expr::Logical(_, KwordType::Or, _) => 2,
+        Expr::Logical(..) => 3,
+        Expr::Bin(_, op, _) => match op.op_type() {
+            Some(OpType::EqualEqual | OpType::BangEqual) => 4,
+            Some(OpType::Plus | OpType::Minus) => 6,
+            Some(OpType::Star | OpType::Slash) => 7,
+            _ => 5,
+        },
+        Expr::Unary(..) => UNARY,
+        Expr::Call(..) | Expr::Get(..) => CALL,
+        _ => 10,
+    }
+}
+
+// Whether an else after this statement would be taken by an if inside it
+fn dangles(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::If(_, _, None) => true,
+        Stmt::If(_, _, Some(else_branch)) => dangles(else_branch),
+        Stmt::While(_, body) => dangles(body),
+        _ => false,
+    }
+}
+
+// Prints the AST back out as Lox source. Any tree can be printed: parentheses are added around
+// an expression that binds more loosely than where it appears allows, and braces around a
+// declaration used as the body of an if, else or while, and around a then branch that would
+// otherwise take the else. Parsing the source gives back the same tree only up to those added
+// groupings and blocks; a tree that already has them comes back exactly.
+#[derive(Default)]
+pub struct SourcePrinter {
+    depth: usize,
+}
+
+impl SourcePrinter {
+    pub fn new() -> Self {
+        SourcePrinter::default()
+    }
+
+    pub fn print(&mut self, stmts: &[Stmt]) -> String {
+        stmts
+            .iter()
+            .map(|stmt| self.visit_stmt(stmt) + "\n")
+            .collect()
+    }
+
+    fn indent(&self) -> String {
+        "    ".repeat(self.depth)
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) -> String {
+        if stmts.is_empty() {
+            return "{}".to_string();
+        }
+
+        self.depth += 1;
+        let mut out = "{\n".to_string();
+        for stmt in stmts {
+            out.push_str(&self.indent());
+            out.push_str(&self.visit_stmt(stmt));
+            out.push('\n');
+        }
+        self.depth -= 1;
+        out.push_str(&self.indent());
+        out.push('}');
+        out
+    }
+
+    // The body of an if, else or while: a block on the same line, or anything else on the next.
+    // Declarations need a block around them there.
+    fn body(&mut self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::Block(stmts) => return format!(" {}", self.block(stmts)),
+            Stmt::Var(..) | Stmt::Function(_) | Stmt::Class(_) => {
+                return format!(" {}", self.block(slice::from_ref(stmt)))
+            }
+            _ => {}
+        }
+
+        self.depth += 1;
+        let out = format!("\n{}{}", self.indent(), self.visit_stmt(stmt));
+        self.depth -= 1;
+        out
+    }
+
+    // A function's name, parameters and body, which methods print without 'fun'
+    fn function(&mut self, decl: &FunctionDecl) -> String {
+        let params: Vec<&str> = decl
+            .params
+            .iter()
+            .map(|param| param.lexeme.as_str())
+            .collect();
+        format!(
+            "{}({}) {}",
+            decl.name.lexeme,
+            params.join(", "),
+            self.block(&decl.body)
+        )
+    }
+
+    // Print an expression, in parentheses if it binds more loosely than the least allowed
+    fn operand(&mut self, expr: &Expr, min: u8) -> String {
+        let out = self.visit_expr(expr);
+        if precedence(expr) < min {
+            format!("({})", out)
+        } else {
+            out
+        }
+    }
+}
+
+impl Visitor<String> for SourcePrinter {
+    fn visit_expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Assign(name, value, _) => format!("{} = {}", name.lexeme, self.visit_expr(value)),
+            // Binary operators are left-associative
+            Expr::Bin(left, op, right) => format!(
+                "{} {} {}",
+                self.operand(left, precedence(expr)),
+                op.lexeme,
+                self.operand(right, precedence(expr) + 1)
+            ),
+            Expr::Call(callee, _, args) => {
+                let args: Vec<String> = args.iter().map(|arg| self.visit_expr(arg)).collect();
+                format!("{}({})", self.operand(callee, CALL), args.join(", "))
+            }
+            Expr::Get(object, name) => format!("{}.{}", self.operand(object, CALL), name.lexeme),
+            Expr::Grouping(expr) => format!("({})", self.visit_expr(expr)),
+            Expr::Lit(LitType::String(s), _) => format!("\"{}\"", s),
+            Expr::Lit(lit, _) => lit.to_string(),
+            Expr::Logical(left, kword, right) => format!(
+                "{} {} {}",
+                self.operand(left, precedence(expr)),
+                kword,
+                self.operand(right, precedence(expr) + 1)
+            ),
+            Expr::Set(object, name, value) => format!(
+                "{}.{} = {}",
+                self.operand(object, CALL),
+                name.lexeme,
+                self.visit_expr(value)
+            ),
+            Expr::Super(_, method, _) => format!("super.{}", method.lexeme),
+            Expr::This(_, _) => "this".to_string(),
+            Expr::Unary(op, operand) => format!("{}{}", op.lexeme, self.operand(operand, UNARY)),
+            Expr::Variable(name, _) => name.lexeme.clone(),
+        }
+    }
+}
+
+impl StmtVisitor<String> for SourcePrinter {
+    fn visit_stmt(&mut self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::Block(stmts) => self.block(stmts),
+            Stmt::Class(decl) => {
+                let mut out = format!("class {}", decl.name.lexeme);
+                if let Some(superclass) = &decl.superclass {
+                    out.push_str(" < ");
+                    out.push_str(&self.visit_expr(superclass));
+                }
+
+                if decl.methods.is_empty() {
+                    out.push_str(" {}");
+                    return out;
+                }
+
+                out.push_str(" {\n");
+                self.depth += 1;
+                for method in &decl.methods {
+                    out.push_str(&self.indent());
+                    out.push_str(&self.function(method));
+                    out.push('\n');
+                }
+                self.depth -= 1;
+                out.push_str(&self.indent());
+                out.push('}');
+                out
+            }
+            Stmt::Expression(expr) => format!("{};", self.visit_expr(expr)),
+            Stmt::Function(decl) => format!("fun {}", self.function(decl)),
+            Stmt::If(condition, then_branch, else_branch) => {
+                let then_branch = match else_branch {
+                    // Without a block, the else would go to an if inside the then branch
+                    Some(_) if dangles(then_branch) => {
+                        format!(" {}", self.block(slice::from_ref(then_branch.as_ref())))
+                    }
+                    _ => self.body(then_branch),
+                };
+                let mut out = format!("if ({}){}", self.visit_expr(condition), then_branch);
+                match else_branch.as_deref() {
+                    // Chains of else ifs stay flat
+                    Some(else_branch @ Stmt::If(..)) => out.push_str(&format!(
+                        "\n{}else {}",
+                        self.indent(),
+                        self.visit_stmt(else_branch)
+                    )),
+                    Some(else_branch) => out.push_str(&format!(
+                        "\n{}else{}",
+                        self.indent(),
+                        self.body(else_branch)
+                    )),
+                    None => {}
+                }
+                out
+            }
+            Stmt::Print(expr) => format!("print {};", self.visit_expr(expr)),
+            Stmt::Return(_, Some(value)) => format!("return {};", self.visit_expr(value)),
+            Stmt::Return(_, None) => "return;".to_string(),
+            Stmt::Var(name, Some(initializer)) => {
+                format!("var {} = {};", name.lexeme, self.visit_expr(initializer))
+            }
+            Stmt::Var(name, None) => format!("var {};", name.lexeme),
+            Stmt::While(condition, body) => {
+                format!("while ({}){}", self.visit_expr(condition), self.body(body))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_printer {
+    use super::*;
+    use crate::interpreter::{lex, parse};
+    use crate::lexer::{KwordType, OpType, Span, TokenType};
+    use crate::parser::ClassDecl;
+    use proptest::collection::vec;
+    use proptest::option;
+    use proptest::prelude::*;
+    use std::rc::Rc;
+
+    fn print_src(src: &str) -> String {
+        AstPrinter::new().print(&parse(src).unwrap())
+    }
+
+    #[test]
+    fn test_print_tokens() {
+        let (tokens, errors) = lex("var a =\n  \"hi\";");
+        assert!(errors.is_empty());
+        assert_eq!(
+            "1:1 Kword(Var) \"var\"\n\
+             1:5 Lit(Identifier(\"a\")) \"a\"\n\
+             1:7 Op(Equal) \"=\"\n\
+             2:3 Lit(String(\"hi\")) \"\\\"hi\\\"\"\n\
+             2:7 Op(Semicolon) \";\"\n\
+             2:8 Eof \"\"\n",
+            print_tokens(&tokens)
+        );
+    }
+
+    #[test]
+    fn test_print_exprs() {
+        assert_eq!("(expr (+ 1 (* 2 3)))\n", print_src("1 + 2 * 3;"));
+        assert_eq!(
+            "(expr (* (- 123) (group 45.67)))\n",
+            print_src("-123 * (45.67);")
+        );
+        assert_eq!("(expr (* (group (+ 1 2)) 3))\n", print_src("(1 + 2) * 3;"));
+        assert_eq!(
+            "(expr (or (and true nil) (! false)))\n",
+            print_src("true and nil or !false;")
+        );
+        assert_eq!(
+            "(expr (= a (call f \"s\" (- 1.5))))\n",
+            print_src("a = f(\"s\", -1.5);")
+        );
+        assert_eq!(
+            "(expr (set (. this a) b (call (. (super m) c))))\n",
+            print_src("this.a.b = super.m.c();")
+        );
+    }
+
+    #[test]
+    fn test_print_stmts() {
+        let src = "var a; var b = 1;\n\
+                   class B < A { init(x, y) { this.x = x; return; } }\n\
+                   fun f() { if (a) print a; else { while (b) b = b - 1; } return b; }";
+        assert_eq!(
+            "(var a)\n\
+             (var b 1)\n\
+             (class B < A\n  \
+               (fun init (x y)\n    \
+                 (expr (set this x x))\n    \
+                 (return)))\n\
+             (fun f ()\n  \
+               (if a\n    \
+                 (print a)\n    \
+                 (block\n      \
+                   (while b\n        \
+                     (expr (= b (- b 1))))))\n  \
+               (return b))\n",
+            print_src(src)
+        );
+    }
+
+    #[test]
+    fn test_print_source() {
+        let src = "class B<A{init(x,y){this.x=x;return;}m(){}}\n\
+                   fun f(){if(a)print a;else if(b){}else while(b)b=b-(1+2)*3;return -f(\"s\").g;}\n\
+                   var a;{var b=nil or true and !false;}";
+        assert_eq!(
+            "class B < A {\n\
+             \x20   init(x, y) {\n\
+             \x20       this.x = x;\n\
+             \x20       return;\n\
+             \x20   }\n\
+             \x20   m() {}\n\
+             }\n\
+             fun f() {\n\
+             \x20   if (a)\n\
+             \x20       print a;\n\
+             \x20   else if (b) {}\n\
+             \x20   else\n\
+             \x20       while (b)\n\
+             \x20           b = b - (1 + 2) * 3;\n\
+             \x20   return -f(\"s\").g;\n\
+             }\n\
+             var a;\n\
+             {\n\
+             \x20   var b = nil or true and !false;\n\
+             }\n",
+            SourcePrinter::new().print(&parse(src).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_print_unparsed_trees() {
+        // An addition under a multiplication, and an assignment under a negation
+        let num = |n: f64| Box::new(Expr::Lit(LitType::Number(n), Span::default()));
+        let op = |op: OpType| token(TokenType::Op(op), &op.to_string());
+        let sum = Expr::Bin(num(1.0), op(OpType::Plus), num(2.0));
+        let assign = Expr::Assign(ident("a"), num(3.0), 0);
+        let product = Expr::Bin(
+            Box::new(sum),
+            op(OpType::Star),
+            Box::new(Expr::Unary(op(OpType::Minus), Box::new(assign))),
+        );
+
+        // A then branch that would take the else, and a declaration as a loop body
+        let print = |name: &str| Box::new(Stmt::Print(Expr::Variable(ident(name), 0)));
+        let inner = Stmt::If(Expr::Variable(ident("b"), 0), print("b"), None);
+        let stmts = [
+            Stmt::Print(product),
+            Stmt::If(
+                Expr::Variable(ident("a"), 0),
+                Box::new(inner),
+                Some(print("a")),
+            ),
+            Stmt::While(
+                Expr::Lit(LitType::True, Span::default()),
+                Box::new(Stmt::Var(ident("x"), None)),
+            ),
+        ];
+
+        assert_eq!(
+            "print (1 + 2) * -(a = 3);\n\
+             if (a) {\n\
+             \x20   if (b)\n\
+             \x20       print b;\n\
+             }\n\
+             else\n\
+             \x20   print a;\n\
+             while (true) {\n\
+             \x20   var x;\n\
+             }\n",
+            SourcePrinter::new().print(&stmts)
+        );
+        assert_round_trip(&stmts);
+    }
+
+    // Print a tree as source and parse it again
+    fn reparse(stmts: &[Stmt]) -> (String, Vec<Stmt>) {
+        let src = SourcePrinter::new().print(stmts);
+        let reparsed = parse(&src)
+            .unwrap_or_else(|errs| panic!("Printed source failed to parse: {:?}\n{}", errs, src));
+        (src, reparsed)
+    }
+
+    // Check that printing a tree as source and parsing it again gives back the same tree, other
+    // than the groupings and blocks the printer adds. Trees are compared as S-expressions, which
+    // leave out locations and resolver ids.
+    fn assert_round_trip(stmts: &[Stmt]) {
+        let (src, reparsed) = reparse(stmts);
+        let bare = |stmts: &[Stmt]| {
+            let stmts: Vec<Stmt> = stmts.iter().map(bare_stmt).collect();
+            AstPrinter::new().print(&stmts)
+        };
+        assert_eq!(bare(stmts), bare(&reparsed), "Printed source:\n{}", src);
+    }
+
+    // A tree without the groupings and blocks the printer may add. Groupings are replaced by
+    // what they group, and a block holding a single statement in the body of an if or while by
+    // that statement.
+    fn bare_expr(expr: &Expr) -> Expr {
+        let bare = |expr: &Expr| Box::new(bare_expr(expr));
+        match expr {
+            Expr::Grouping(expr) => bare_expr(expr),
+            Expr::Assign(name, value, id) => Expr::Assign(name.clone(), bare(value), *id),
+            Expr::Bin(left, op, right) => Expr::Bin(bare(left), op.clone(), bare(right)),
+            Expr::Call(callee, paren, args) => Expr::Call(
+                bare(callee),
+                paren.clone(),
+                args.iter().map(bare_expr).collect(),
+            ),
+            Expr::Get(object, name) => Expr::Get(bare(object), name.clone()),
+            Expr::Logical(left, kword, right) => Expr::Logical(bare(left), *kword, bare(right)),
+            Expr::Set(object, name, value) => Expr::Set(bare(object), name.clone(), bare(value)),
+            Expr::Unary(op, operand) => Expr::Unary(op.clone(), bare(operand)),
+            expr => expr.clone(),
+        }
+    }
+
+    fn bare_stmt(stmt: &Stmt) -> Stmt {
+        let body = |stmt: &Stmt| {
+            Box::new(match stmt {
+                Stmt::Block(stmts) if stmts.len() == 1 => bare_stmt(&stmts[0]),
+                stmt => bare_stmt(stmt),
+            })
+        };
+        let function = |decl: &Rc<FunctionDecl>| {
+            Rc::new(FunctionDecl {
+                name: decl.name.clone(),
+                params: decl.params.clone(),
+                body: decl.body.iter().map(bare_stmt).collect(),
+            })
+        };
+
+        match stmt {
+            Stmt::Block(stmts) => Stmt::Block(stmts.iter().map(bare_stmt).collect()),
+            Stmt::Class(decl) => Stmt::Class(ClassDecl {
+                name: decl.name.clone(),
+                superclass: decl.superclass.clone(),
+                methods: decl.methods.iter().map(function).collect(),
+            }),
+            Stmt::Expression(expr) => Stmt::Expression(bare_expr(expr)),
+            Stmt::Function(decl) => Stmt::Function(function(decl)),
+            Stmt::If(condition, then_branch, else_branch) => Stmt::If(
+                bare_expr(condition),
+                body(then_branch),
+                else_branch.as_deref().map(body),
+            ),
+            Stmt::Print(expr) => Stmt::Print(bare_expr(expr)),
+            Stmt::Return(keyword, value) => {
+                Stmt::Return(keyword.clone(), value.as_ref().map(bare_expr))
+            }
+            Stmt::Var(name, initializer) => {
+                Stmt::Var(name.clone(), initializer.as_ref().map(bare_expr))
+            }
+            Stmt::While(condition, body_stmt) => Stmt::While(bare_expr(condition), body(body_stmt)),
+        }
+    }
+
+    // Check that a tree which already has every grouping and block the printer would add comes
+    // back exactly, and prints the same the second time round
+    fn assert_exact_round_trip(stmts: &[Stmt]) {
+        let (src, reparsed) = reparse(stmts);
+        assert_eq!(
+            AstPrinter::new().print(stmts),
+            AstPrinter::new().print(&reparsed),
+            "Printed source:\n{}",
+            src
+        );
+        assert_eq!(src, SourcePrinter::new().print(&reparsed));
+    }
+
+    // A tree with the groupings and blocks the printer would add made explicit, the inverse of
+    // bare_stmt
+    fn grouped_expr(expr: &Expr) -> Expr {
+        let operand = |operand: &Expr, min: u8| {
+            let operand = grouped_expr(operand);
+            if precedence(&operand) < min {
+                Box::new(Expr::Grouping(Box::new(operand)))
+            } else {
+                Box::new(operand)
+            }
+        };
+        match expr {
+            Expr::Assign(name, value, id) => {
+                Expr::Assign(name.clone(), Box::new(grouped_expr(value)), *id)
+            }
+            Expr::Bin(left, op, right) => Expr::Bin(
+                operand(left, precedence(expr)),
+                op.clone(),
+                operand(right, precedence(expr) + 1),
+            ),
+            Expr::Call(callee, paren, args) => Expr::Call(
+                operand(callee, CALL),
+                paren.clone(),
+                args.iter().map(grouped_expr).collect(),
+            ),
+            Expr::Get(object, name) => Expr::Get(operand(object, CALL), name.clone()),
+            Expr::Grouping(expr) => Expr::Grouping(Box::new(grouped_expr(expr))),
+            Expr::Logical(left, kword, right) => Expr::Logical(
+                operand(left, precedence(expr)),
+                *kword,
+                operand(right, precedence(expr) + 1),
+            ),
+            Expr::Set(object, name, value) => Expr::Set(
+                operand(object, CALL),
+                name.clone(),
+                Box::new(grouped_expr(value)),
+            ),
+            Expr::Unary(op, operand_expr) => Expr::Unary(op.clone(), operand(operand_expr, UNARY)),
+            expr => expr.clone(),
+        }
+    }
+
+    fn grouped_stmt(stmt: &Stmt) -> Stmt {
+        let block = |stmt: &Stmt| Box::new(Stmt::Block(vec![grouped_stmt(stmt)]));
+        let body = |stmt: &Stmt| match stmt {
+            Stmt::Var(..) | Stmt::Function(_) | Stmt::Class(_) => block(stmt),
+            stmt => Box::new(grouped_stmt(stmt)),
+        };
+        let function = |decl: &Rc<FunctionDecl>| {
+            Rc::new(FunctionDecl {
+                name: decl.name.clone(),
+                params: decl.params.clone(),
+                body: decl.body.iter().map(grouped_stmt).collect(),
+            })
+        };
+
+        match stmt {
+            Stmt::Block(stmts) => Stmt::Block(stmts.iter().map(grouped_stmt).collect()),
+            Stmt::Class(decl) => Stmt::Class(ClassDecl {
+                name: decl.name.clone(),
+                superclass: decl.superclass.clone(),
+                methods: decl.methods.iter().map(function).collect(),
+            }),
+            Stmt::Expression(expr) => Stmt::Expression(grouped_expr(expr)),
+            Stmt::Function(decl) => Stmt::Function(function(decl)),
+            Stmt::If(condition, then_branch, else_branch) => {
+                let then_branch = match else_branch {
+                    Some(_) if dangles(&grouped_stmt(then_branch)) => block(then_branch),
+                    _ => body(then_branch),
+                };
+                Stmt::If(
+                    grouped_expr(condition),
+                    then_branch,
+                    else_branch.as_deref().map(body),
+                )
+            }
+            Stmt::Print(expr) => Stmt::Print(grouped_expr(expr)),
+            Stmt::Return(keyword, value) => {
+                Stmt::Return(keyword.clone(), value.as_ref().map(grouped_expr))
+            }
+            Stmt::Var(name, initializer) => {
+                Stmt::Var(name.clone(), initializer.as_ref().map(grouped_expr))
+            }
+            Stmt::While(condition, body_stmt) => {
+                Stmt::While(grouped_expr(condition), body(body_stmt))
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip_files() {
+        let files = [
+            include_str!("../tests/data/1_hello.lox"),
+            include_str!("../tests/data/2_scopes_and_vars.lox"),
+            include_str!("../tests/data/3_control_flow.lox"),
+            include_str!("../tests/data/4_functions.lox"),
+        ];
+        for src in files {
+            assert_round_trip(&parse(src).unwrap());
+        }
+    }
+
+    fn token(token_type: TokenType, lexeme: &str) -> Token {
+        Token::new(token_type, lexeme.to_string(), Span::default())
+    }
+
+    fn ident(name: &str) -> Token {
+        token(TokenType::Lit(LitType::Identifier(name.to_string())), name)
+    }
+
+    fn arb_name() -> impl Strategy<Value = String> {
+        "[a-z_][a-z0-9_]{0,4}".prop_filter("keywords aren't names", |name| {
+            !KwordType::ALL
+                .iter()
+                .any(|kword| kword.to_string() == *name)
+        })
+    }
+
+    fn arb_primary() -> impl Strategy<Value = Expr> {
+        prop_oneof![
+            Just(Expr::Lit(LitType::True, Span::default())),
+            Just(Expr::Lit(LitType::False, Span::default())),
+            Just(Expr::Lit(LitType::Nil, Span::default())),
+            (0.0..1e9f64).prop_map(|n| Expr::Lit(LitType::Number(n), Span::default())),
+            "[^\"]{0,8}".prop_map(|s| Expr::Lit(LitType::String(s), Span::default())),
+            arb_name().prop_map(|name| Expr::Variable(ident(&name), 0)),
+            Just(Expr::This(
+                token(TokenType::Kword(KwordType::This), "this"),
+                0
+            )),
+            arb_name().prop_map(|method| {
+                let keyword = token(TokenType::Kword(KwordType::Super), "super");
+                Expr::Super(keyword, ident(&method), 0)
+            }),
+        ]
+    }
+
+    fn arb_bin_op() -> impl Strategy<Value = Token> {
+        prop_oneof![
+            Just(OpType::EqualEqual),
+            Just(OpType::BangEqual),
+            Just(OpType::Less),
+            Just(OpType::LessEqual),
+            Just(OpType::Greater),
+            Just(OpType::GreaterEqual),
+            Just(OpType::Plus),
+            Just(OpType::Minus),
+            Just(OpType::Star),
+            Just(OpType::Slash),
+        ]
+        .prop_map(|op| token(TokenType::Op(op), &op.to_string()))
+    }
+
+    fn arb_expr() -> impl Strategy<Value = Expr> {
+        arb_primary().prop_recursive(4, 24, 3, |inner| {
+            let paren = token(TokenType::Op(OpType::RightParen), ")");
+            prop_oneof![
+                (inner.clone(), arb_bin_op(), inner.clone()).prop_map(|(l, op, r)| Expr::Bin(
+                    Box::new(l),
+                    op,
+                    Box::new(r)
+                )),
+                (inner.clone(), any::<bool>(), inner.clone()).prop_map(|(l, and, r)| {
+                    let kword = if and { KwordType::And } else { KwordType::Or };
+                    Expr::Logical(Box::new(l), kword, Box::new(r))
+                }),
+                (any::<bool>(), inner.clone()).prop_map(|(minus, operand)| {
+                    let op = if minus { OpType::Minus } else { OpType::Bang };
+                    Expr::Unary(token(TokenType::Op(op), &op.to_string()), Box::new(operand))
+                }),
+                (inner.clone(), vec(inner.clone(), 0..3)).prop_map(move |(callee, args)| {
+                    Expr::Call(Box::new(callee), paren.clone(), args)
+                }),
+                (inner.clone(), arb_name())
+                    .prop_map(|(object, name)| Expr::Get(Box::new(object), ident(&name))),
+                (inner.clone(), arb_name(), inner.clone()).prop_map(|(object, name, value)| {
+                    Expr::Set(Box::new(object), ident(&name), Box::new(value))
+                }),
+                (arb_name(), inner.clone()).prop_map(|(name, value)| Expr::Assign(
+                    ident(&name),
+                    Box::new(value),
+                    0
+                )),
+                inner.prop_map(|expr| Expr::Grouping(Box::new(expr))),
+            ]
+        })
+    }
+
+    fn arb_function(body: BoxedStrategy<Stmt>) -> impl Strategy<Value = FunctionDecl> {
+        (arb_name(), vec(arb_name(), 0..3), vec(body, 0..3)).prop_map(|(name, params, body)| {
+            FunctionDecl {
+                name: ident(&name),
+                params: params.iter().map(|param| ident(param)).collect(),
+                body,
+            }
+        })
+    }
+
+    fn arb_stmt() -> impl Strategy<Value = Stmt> {
+        let keyword = |kword: KwordType| token(TokenType::Kword(kword), &kword.to_string());
+        let leaf = prop_oneof![
+            arb_expr().prop_map(Stmt::Expression),
+            arb_expr().prop_map(Stmt::Print),
+            option::of(arb_expr())
+                .prop_map(move |value| Stmt::Return(keyword(KwordType::Return), value)),
+            (arb_name(), option::of(arb_expr()))
+                .prop_map(|(name, initializer)| Stmt::Var(ident(&name), initializer)),
+        ];
+
+        leaf.prop_recursive(3, 16, 3, |inner| {
+            prop_oneof![
+                vec(inner.clone(), 0..3).prop_map(Stmt::Block),
+                (arb_expr(), inner.clone(), option::of(inner.clone())).prop_map(
+                    |(condition, then_branch, else_branch)| {
+                        Stmt::If(condition, Box::new(then_branch), else_branch.map(Box::new))
+                    }
+                ),
+                (arb_expr(), inner.clone())
+                    .prop_map(|(condition, body)| Stmt::While(condition, Box::new(body))),
+                arb_function(inner.clone()).prop_map(|decl| Stmt::Function(Rc::new(decl))),
+                (
+                    arb_name(),
+                    option::of(arb_name()),
+                    vec(arb_function(inner), 0..3)
+                )
+                    .prop_map(|(name, superclass, methods)| {
+                        Stmt::Class(ClassDecl {
+                            name: ident(&name),
+                            superclass: superclass.map(|name| Expr::Variable(ident(&name), 0)),
+                            methods: methods.into_iter().map(Rc::new).collect(),
+                        })
+                    }),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn test_round_trip(stmts in vec(arb_stmt(), 0..4)) {
+            assert_round_trip(&stmts);
+        }
+
+        #[test]
+        fn test_exact_round_trip(stmts in vec(arb_stmt(), 0..4)) {
+            let stmts: Vec<Stmt> = stmts.iter().map(grouped_stmt).collect();
+            assert_exact_round_trip(&stmts);
+        }
+    }
+}
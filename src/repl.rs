@@ -1,41 +1,516 @@
-use crate::interpreter::{exec_line, report_err};
-use std::io::{stdin, BufRead, BufReader, Read, Write};
+use crate::diagnostics::{self, ErrorFormat};
+use crate::interpreter::{lex, parse, Interpreter, LoxError};
+use crate::lexer::{KwordType, LexErrorKind, Lexer, LitType, Token, TokenType};
+use crate::parser::Parser;
+use crate::printer::{print_tokens, AstPrinter};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::FileHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::borrow::Cow;
+use std::ffi::OsString;
+use std::io::{stdin, BufRead, IsTerminal, StdinLock, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+use std::{env, fs};
 
-// Start a Lox REPL that will continually interpret lines until it receives the 'exit/quit' command
+// The commands the REPL handles itself, with their usage and what they do
+const COMMANDS: [(&str, &str); 8] = [
+    (":tokens <src>", "Show the tokens lexed from source code"),
+    (":ast <src>", "Show the syntax tree parsed from source code"),
+    (":load <file>", "Run a file in this session"),
+    (":env", "List the global bindings"),
+    (":time <src>", "Run source code and show how long it took"),
+    (":reset", "Forget everything defined in this session"),
+    (":help", "Show this message"),
+    (":quit", "Leave the REPL, as do 'exit' and 'quit'"),
+];
+
+// Start a Lox REPL that will continually interpret input until it receives the 'exit/quit' command
+// or the end of input. Input that is cut off mid-statement is continued on the next line, and
+// the value of a lone expression is printed. Everything defined lasts the whole session, and
+// lines starting with ':' are commands to the REPL itself.
 pub fn run_repl() {
-    let mut line_no: u32 = 1;
+    let mut interpreter = Interpreter::new();
+    let mut input = Input::new();
+    input.set_globals(&interpreter);
+    let mut stdout = std::io::stdout();
+    let mut entry_no: u32 = 1;
+    let mut buffer = String::new();
+
     loop {
-        print_prompt(line_no);
-        let line = read_line(stdin());
-        if line == "exit" || line == "quit" {
-            break;
+        let line = match input.read(&prompt(entry_no, !buffer.is_empty())) {
+            Ok(line) => line,
+            // Ctrl-C throws away the entry being typed
+            Err(ReadlineError::Interrupted) => {
+                buffer.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("Failed to read input: {}", err);
+                break;
+            }
+        };
+
+        if buffer.is_empty() {
+            if line == "exit" || line == "quit" {
+                break;
+            } else if line.trim().is_empty() {
+                continue;
+            } else if line.trim_start().starts_with(':') {
+                match Command::parse(&line) {
+                    Ok(Command::Quit) => break,
+                    Ok(command) => run_command(command, &mut interpreter, &mut stdout),
+                    Err(msg) => eprintln!("{}", msg),
+                }
+
+                input.set_globals(&interpreter);
+                entry_no += 1;
+                continue;
+            }
+        }
+
+        // A blank line runs whatever has been entered so far, complete or not
+        if !line.trim().is_empty() {
+            buffer.push_str(&line);
+            buffer.push('\n');
+
+            if !is_expression(&buffer) && is_incomplete(&buffer) {
+                continue;
+            }
+        }
+
+        run_entry(&mut interpreter, &buffer, &mut stdout);
+        input.set_globals(&interpreter);
+        buffer.clear();
+        entry_no += 1;
+    }
+
+    input.save_history();
+}
+
+// Run an entry, printing its value if it is a lone expression
+fn run_entry(interpreter: &mut Interpreter, src: &str, out: &mut impl Write) {
+    let result = if is_expression(src) {
+        interpreter
+            .run_expression(src)
+            .map(|value| writeln!(out, "=> {}", value).unwrap())
+    } else {
+        interpreter.run(src)
+    };
+
+    if let Err(err) = result {
+        diagnostics::report(&err, ErrorFormat::Human, "<repl>", src);
+    }
+}
+
+// A colon-prefixed command to the REPL itself
+#[derive(Debug, PartialEq)]
+enum Command {
+    Tokens(String),
+    Ast(String),
+    Load(String),
+    Env,
+    Time(String),
+    Reset,
+    Help,
+    Quit,
+}
+
+impl Command {
+    // Parse a line starting with ':' into a command, or describe what's wrong with it
+    fn parse(line: &str) -> Result<Command, String> {
+        let line = line.trim();
+        let (name, arg) = match line.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (line, ""),
+        };
+
+        let command = match name {
+            ":tokens" => Command::Tokens(arg.to_string()),
+            ":ast" => Command::Ast(arg.to_string()),
+            ":load" => Command::Load(arg.to_string()),
+            ":env" => Command::Env,
+            ":time" => Command::Time(arg.to_string()),
+            ":reset" => Command::Reset,
+            ":help" => Command::Help,
+            ":quit" => Command::Quit,
+            _ => {
+                return Err(format!(
+                    "Unknown command '{}'. Type :help for a list.",
+                    name
+                ))
+            }
+        };
+
+        let takes_arg = matches!(
+            command,
+            Command::Tokens(_) | Command::Ast(_) | Command::Load(_) | Command::Time(_)
+        );
+        if takes_arg && arg.is_empty() {
+            let usage = COMMANDS.iter().find(|(usage, _)| usage.starts_with(name));
+            return Err(format!("Usage: {}", usage.unwrap().0));
+        } else if !takes_arg && !arg.is_empty() {
+            return Err(format!("{} doesn't take an argument.", name));
+        }
+
+        Ok(command)
+    }
+}
+
+// Run a command, writing what it shows to the given output
+fn run_command(command: Command, interpreter: &mut Interpreter, out: &mut impl Write) {
+    match command {
+        Command::Tokens(src) => {
+            let (tokens, errors) = lex(&src);
+            write!(out, "{}", print_tokens(&tokens)).unwrap();
+            if !errors.is_empty() {
+                diagnostics::report(
+                    &LoxError::Compile(errors),
+                    ErrorFormat::Human,
+                    "<repl>",
+                    &src,
+                );
+            }
+        }
+        Command::Ast(src) => match parse(&src) {
+            Ok(stmts) => write!(out, "{}", AstPrinter::new().print(&stmts)).unwrap(),
+            Err(errs) => {
+                diagnostics::report(&LoxError::Compile(errs), ErrorFormat::Human, "<repl>", &src)
+            }
+        },
+        Command::Load(path) => match fs::read_to_string(&path) {
+            Ok(src) => {
+                if let Err(err) = interpreter.run(&src) {
+                    diagnostics::report(&err, ErrorFormat::Human, &path, &src);
+                }
+            }
+            Err(err) => eprintln!("Failed to read {}: {}", path, err),
+        },
+        Command::Env => {
+            for (name, value) in interpreter.globals() {
+                writeln!(out, "{} = {}", name, value).unwrap();
+            }
+        }
+        Command::Time(src) => {
+            let start = Instant::now();
+            run_entry(interpreter, &src, out);
+            writeln!(out, "took {:?}", start.elapsed()).unwrap();
+        }
+        Command::Reset => *interpreter = Interpreter::new(),
+        Command::Help => {
+            for (usage, description) in COMMANDS {
+                writeln!(out, "{:<16}{}", usage, description).unwrap();
+            }
+        }
+        Command::Quit => {}
+    }
+}
+
+// Where the REPL reads lines from: a line editor with history and completion at a terminal,
+// or plain lines when input is piped in
+enum Input {
+    Editor(Box<Editor<LoxHelper, FileHistory>>),
+    Plain(StdinLock<'static>),
+}
+
+impl Input {
+    fn new() -> Self {
+        if !stdin().is_terminal() {
+            return Input::Plain(stdin().lock());
+        }
+
+        match Editor::new() {
+            Ok(mut editor) => {
+                editor.set_helper(Some(LoxHelper {
+                    globals: Vec::new(),
+                    color: env::var_os("NO_COLOR").is_none(),
+                }));
+                let path = history_path(env::var_os("XDG_DATA_HOME"), env::var_os("HOME"));
+                if let Some(path) = path {
+                    // There is no history to load the first time the REPL is run
+                    editor.load_history(&path).ok();
+                }
+                Input::Editor(Box::new(editor))
+            }
+            Err(_) => Input::Plain(stdin().lock()),
+        }
+    }
+
+    // Read a line after showing the prompt, with the line ending stripped
+    fn read(&mut self, prompt: &str) -> Result<String, ReadlineError> {
+        match self {
+            Input::Editor(editor) => {
+                let line = editor.readline(prompt)?;
+                if !line.trim().is_empty() {
+                    editor.add_history_entry(line.as_str())?;
+                }
+                Ok(line)
+            }
+            Input::Plain(reader) => {
+                print!("{}", prompt);
+                std::io::stdout().flush()?;
+                read_line(reader).ok_or(ReadlineError::Eof)
+            }
+        }
+    }
+
+    // Update the global names offered by tab completion
+    fn set_globals(&mut self, interpreter: &Interpreter) {
+        if let Input::Editor(editor) = self {
+            if let Some(helper) = editor.helper_mut() {
+                helper.globals = interpreter
+                    .globals()
+                    .into_iter()
+                    .map(|(name, _)| name)
+                    .collect();
+            }
+        }
+    }
+
+    fn save_history(&mut self) {
+        let Input::Editor(editor) = self else {
+            return;
+        };
+        let Some(path) = history_path(env::var_os("XDG_DATA_HOME"), env::var_os("HOME")) else {
+            return;
+        };
+
+        let saved = match path.parent() {
+            Some(dir) => fs::create_dir_all(dir).map_err(ReadlineError::from),
+            None => Ok(()),
+        }
+        .and_then(|_| editor.save_history(&path));
+        if let Err(err) = saved {
+            eprintln!("Failed to save history to {}: {}", path.display(), err);
+        }
+    }
+}
+
+// Where history is kept between sessions: under $XDG_DATA_HOME, or ~/.local/share without it
+fn history_path(xdg_data_home: Option<OsString>, home: Option<OsString>) -> Option<PathBuf> {
+    // Relative paths in XDG variables are invalid and should be ignored
+    let data_dir = xdg_data_home
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| {
+            home.filter(|home| !home.is_empty())
+                .map(|home| PathBuf::from(home).join(".local/share"))
+        })?;
+
+    Some(data_dir.join("rlox").join("history"))
+}
+
+// Tab completion of keywords, global names and commands, and syntax highlighting, for the line
+// editor
+#[derive(Default)]
+struct LoxHelper {
+    globals: Vec<String>,
+    color: bool,
+}
+
+impl LoxHelper {
+    // Find where the word before the cursor starts, and everything it could be completed to
+    fn completions(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let before = &line[..pos];
+        let start = before
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .map_or(0, |i| i + before[i..].chars().next().unwrap().len_utf8());
+        // A ':' at the start of the line begins a command
+        let start = match before[..start].strip_suffix(':') {
+            Some(rest) if rest.trim().is_empty() => start - 1,
+            _ => start,
+        };
+        let word = &before[start..];
+        if word.is_empty() {
+            return (pos, Vec::new());
+        }
+
+        let keywords = KwordType::ALL.iter().map(|kword| kword.to_string());
+        let globals = self.globals.iter().cloned();
+        // Commands are only recognized on a line of their own
+        let commands = COMMANDS
+            .iter()
+            .map(|(usage, _)| usage.split(' ').next().unwrap())
+            .chain(["exit", "quit"])
+            .filter(|_| before[..start].trim().is_empty())
+            .map(|cmd| cmd.to_string());
+
+        let mut candidates: Vec<String> = keywords
+            .chain(globals)
+            .chain(commands)
+            .filter(|candidate| candidate.starts_with(word))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+
+        (start, candidates)
+    }
+}
+
+impl Completer for LoxHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.completions(line, pos))
+    }
+}
+
+impl Hinter for LoxHelper {
+    type Hint = String;
+}
+
+impl Highlighter for LoxHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        if self.color {
+            Cow::Owned(highlight(line))
         } else {
-            exec_line(line).unwrap_or_else(|e| {
-                report_err(line_no, e.as_str());
-            });
+            Cow::Borrowed(line)
         }
+    }
 
-        line_no += 1;
+    // Every keystroke can change how the line lexes
+    fn highlight_char(&self, _line: &str, _pos: usize, _forced: bool) -> bool {
+        self.color
     }
 }
 
-// Print a prompt to the user and flush stdout
-fn print_prompt(line_no: u32) {
-    print!("[🦀 lox] [{}] > ", line_no);
-    std::io::stdout().flush().unwrap();
+// ANSI colors for each kind of source text
+const KEYWORD: &str = "\x1b[35m";
+const STRING: &str = "\x1b[32m";
+const NUMBER: &str = "\x1b[33m";
+const IDENTIFIER: &str = "\x1b[36m";
+const COMMENT: &str = "\x1b[90m";
+const ERROR: &str = "\x1b[1;31m";
+const RESET: &str = "\x1b[0m";
+
+// Color source code by how the lexer classifies it
+fn highlight(src: &str) -> String {
+    let mut out = String::with_capacity(src.len());
+    // Where the text not yet written starts
+    let mut written = 0;
+
+    for result in Lexer::new(src).lex_tokens() {
+        let (span, color) = match result {
+            Ok(token) => (
+                token.span,
+                match token.token_type {
+                    TokenType::Kword(_)
+                    | TokenType::Lit(LitType::True | LitType::False | LitType::Nil) => {
+                        Some(KEYWORD)
+                    }
+                    TokenType::Lit(LitType::String(_)) => Some(STRING),
+                    TokenType::Lit(LitType::Number(_)) => Some(NUMBER),
+                    TokenType::Lit(LitType::Identifier(_)) => Some(IDENTIFIER),
+                    TokenType::Op(_) | TokenType::Eof => None,
+                },
+            ),
+            // A string still being typed is unterminated, which isn't worth flagging
+            Err(err) => (
+                err.span,
+                match err.kind {
+                    LexErrorKind::UnterminatedString => Some(STRING),
+                    LexErrorKind::UnexpectedCharacter => Some(ERROR),
+                },
+            ),
+        };
+
+        let start = span.byte_offset.max(written);
+        let end = (span.byte_offset + span.len).max(start);
+        highlight_gap(&src[written..start], &mut out);
+        match color {
+            Some(color) if start < end => {
+                out.push_str(color);
+                out.push_str(&src[start..end]);
+                out.push_str(RESET);
+            }
+            _ => out.push_str(&src[start..end]),
+        }
+        written = end;
+    }
+
+    highlight_gap(&src[written..], &mut out);
+    out
+}
+
+// Color the text between tokens, which is whitespace and comments
+fn highlight_gap(gap: &str, out: &mut String) {
+    let mut rest = gap;
+    while let Some(start) = rest.find("//") {
+        let end = rest[start..].find('\n').map_or(rest.len(), |i| start + i);
+        out.push_str(&rest[..start]);
+        out.push_str(COMMENT);
+        out.push_str(&rest[start..end]);
+        out.push_str(RESET);
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+}
+
+impl Validator for LoxHelper {}
+
+impl Helper for LoxHelper {}
+
+// The prompt for an entry; continuation lines get their own prompt
+fn prompt(entry_no: u32, continuation: bool) -> String {
+    if continuation {
+        format!("[🦀 lox] [{}] ... ", entry_no)
+    } else {
+        format!("[🦀 lox] [{}] > ", entry_no)
+    }
 }
 
-// Read a line and return it
-fn read_line(read_src: impl Read) -> String {
-    let mut reader = BufReader::new(read_src);
+// Read a line and return it, or None at the end of input
+fn read_line(reader: &mut impl BufRead) -> Option<String> {
     let mut buffer = String::new();
-    reader.read_line(&mut buffer).unwrap();
+    if reader.read_line(&mut buffer).unwrap() == 0 {
+        return None;
+    }
+
     // Strip CRLF or LF
-    buffer
-        .strip_suffix("\r\n")
-        .or_else(|| buffer.strip_suffix('\n'))
-        .unwrap_or(buffer.as_str())
-        .to_string()
+    Some(
+        buffer
+            .strip_suffix("\r\n")
+            .or_else(|| buffer.strip_suffix('\n'))
+            .unwrap_or(buffer.as_str())
+            .to_string(),
+    )
+}
+
+// Return whether source code is a lone expression without a ';'
+fn is_expression(src: &str) -> bool {
+    let tokens: Result<Vec<Token>, _> = Lexer::new(src).lex_tokens().into_iter().collect();
+
+    tokens.is_ok_and(|tokens| Parser::new(tokens).parse_expression().is_ok())
+}
+
+// Return whether source code stops partway through: inside a string, or with every syntax
+// error at the end of the input, as when a brace or parenthesis is left open or a ';' is missing
+fn is_incomplete(src: &str) -> bool {
+    let mut tokens = Vec::new();
+    for result in Lexer::new(src).lex_tokens() {
+        match result {
+            Ok(token) => tokens.push(token),
+            Err(err) if err.kind == LexErrorKind::UnterminatedString => return true,
+            Err(_) => return false,
+        }
+    }
+
+    match Parser::new(tokens).parse_tokens() {
+        Ok(_) => false,
+        Err(errs) => errs
+            .iter()
+            .all(|err| err.token.token_type == TokenType::Eof),
+    }
 }
 
 #[cfg(test)]
@@ -47,11 +522,211 @@ mod test_repl {
         let inputs = vec!["abc\r\n", "abc\n", "abc"];
 
         for input in inputs {
-            let abc = read_line(input.as_bytes());
-            assert_eq!("abc", abc);
+            let abc = read_line(&mut input.as_bytes());
+            assert_eq!(Some("abc".to_string()), abc);
+        }
+
+        // Test that reading an empty line is ok, and that the end of input is reported
+        assert_eq!(Some("".to_string()), read_line(&mut "\n".as_bytes()));
+        assert_eq!(None, read_line(&mut "".as_bytes()));
+    }
+
+    #[test]
+    fn test_is_incomplete() {
+        let incomplete = vec![
+            "fun f() {",
+            "class A {\n  init() {\n",
+            "print (1 +",
+            "print \"hello",
+            "var a = 1",
+            "if (a) print a; else",
+        ];
+        for src in incomplete {
+            assert!(is_incomplete(src), "{:?} should be incomplete", src);
+        }
+
+        let complete = vec![
+            "print 1;",
+            "fun f() {}",
+            "",
+            // Errors before the end of the input can't be fixed by typing more
+            "print 1 + ; {",
+            "}",
+            "var a = 1 @",
+        ];
+        for src in complete {
+            assert!(!is_incomplete(src), "{:?} should be complete", src);
         }
+    }
+
+    #[test]
+    fn test_is_expression() {
+        assert!(is_expression("1 + 2"));
+        assert!(is_expression("a = f(b)\n"));
+        assert!(!is_expression("1 + 2;"));
+        assert!(!is_expression("print 1"));
+        assert!(!is_expression("1 +"));
+        assert!(!is_expression(""));
+    }
+
+    #[test]
+    fn test_completions() {
+        let helper = LoxHelper {
+            globals: vec!["clock".to_string(), "counter".to_string()],
+            color: false,
+        };
+
+        assert_eq!((0, vec!["class".to_string()]), helper.completions("cla", 3));
+        assert_eq!(
+            (6, vec!["class".to_string(), "clock".to_string()]),
+            helper.completions("print cl", 8)
+        );
+        // Only the word before the cursor is completed
+        assert_eq!((0, vec!["fun".to_string()]), helper.completions("fu(1)", 2));
+        assert_eq!((2, Vec::<String>::new()), helper.completions("a ", 2));
+
+        // Commands are only offered at the start of a line
+        assert_eq!((0, vec!["exit".to_string()]), helper.completions("ex", 2));
+        assert_eq!((6, Vec::<String>::new()), helper.completions("print ex", 8));
+    }
+
+    #[test]
+    fn test_command_completions() {
+        let helper = LoxHelper::default();
+        assert_eq!(
+            (0, vec![":reset".to_string()]),
+            helper.completions(":re", 3)
+        );
+        assert_eq!((2, COMMANDS.len()), {
+            let (start, candidates) = helper.completions("  :", 3);
+            (start, candidates.len())
+        });
+        // Elsewhere a ':' is just punctuation
+        assert_eq!(
+            (3, vec!["this".to_string(), "true".to_string()]),
+            helper.completions("a :t", 4)
+        );
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            Ok(Command::Tokens("var a = 1;".to_string())),
+            Command::parse(":tokens   var a = 1;")
+        );
+        assert_eq!(Ok(Command::Env), Command::parse(" :env "));
+        assert_eq!(
+            Ok(Command::Load("a.lox".to_string())),
+            Command::parse(":load a.lox")
+        );
+
+        assert_eq!(
+            Err("Usage: :time <src>".to_string()),
+            Command::parse(":time")
+        );
+        assert_eq!(
+            Err(":reset doesn't take an argument.".to_string()),
+            Command::parse(":reset now")
+        );
+        assert_eq!(
+            Err("Unknown command ':x'. Type :help for a list.".to_string()),
+            Command::parse(":x 1")
+        );
+    }
 
-        // Test that reading an empty line is ok
-        read_line("".as_bytes());
+    // Run a command and return what it wrote
+    fn command_output(command: Command, interpreter: &mut Interpreter) -> String {
+        let mut out = Vec::new();
+        run_command(command, interpreter, &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_run_command() {
+        let mut interpreter = Interpreter::new();
+
+        assert_eq!(
+            "1:1 Kword(Var) \"var\"\n1:5 Lit(Identifier(\"a\")) \"a\"\n1:6 Eof \"\"\n",
+            command_output(Command::Tokens("var a".to_string()), &mut interpreter)
+        );
+
+        assert_eq!(
+            "(print (+ 1 2))\n",
+            command_output(Command::Ast("print 1 + 2;".to_string()), &mut interpreter)
+        );
+
+        interpreter.run("var a = 1;").unwrap();
+        assert_eq!(
+            "a = 1\nclock = <native fn>\ngcStats = <native fn>\n",
+            command_output(Command::Env, &mut interpreter)
+        );
+
+        let output = command_output(Command::Time("a + 1".to_string()), &mut interpreter);
+        assert!(output.starts_with("=> 2\ntook "), "{:?}", output);
+
+        command_output(Command::Reset, &mut interpreter);
+        assert_eq!(
+            "clock = <native fn>\ngcStats = <native fn>\n",
+            command_output(Command::Env, &mut interpreter)
+        );
+
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/4_functions.lox");
+        command_output(Command::Load(path.to_string()), &mut interpreter);
+        assert!(interpreter
+            .globals()
+            .iter()
+            .any(|(name, _)| name == "printSum"));
+    }
+
+    #[test]
+    fn test_highlight() {
+        assert_eq!(
+            "\x1b[35mvar\x1b[0m \x1b[36ma\x1b[0m = \x1b[33m1.5\x1b[0m;",
+            highlight("var a = 1.5;")
+        );
+        assert_eq!(
+            "\x1b[35mprint\x1b[0m \x1b[32m\"hi\"\x1b[0m \x1b[90m// greet\x1b[0m",
+            highlight("print \"hi\" // greet")
+        );
+        assert_eq!(
+            "\x1b[35mtrue\x1b[0m \x1b[35mand\x1b[0m \x1b[35mnil\x1b[0m",
+            highlight("true and nil")
+        );
+
+        // Characters the lexer rejects stand out, while strings still being typed don't
+        assert_eq!(
+            "\x1b[36ma\x1b[0m \x1b[1;31m@\x1b[0m \x1b[36mb\x1b[0m",
+            highlight("a @ b")
+        );
+        assert_eq!(
+            "\x1b[35mprint\x1b[0m \x1b[32m\"unfinished\x1b[0m",
+            highlight("print \"unfinished")
+        );
+
+        // The text itself is never changed
+        let src = "fun f(a) {\n  // ünïcode\n  return a * 2; } # \"x\n";
+        let plain = highlight(src).replace(RESET, "");
+        let plain = [KEYWORD, STRING, NUMBER, IDENTIFIER, COMMENT, ERROR]
+            .iter()
+            .fold(plain, |text, color| text.replace(color, ""));
+        assert_eq!(src, plain);
+    }
+
+    #[test]
+    fn test_history_path() {
+        assert_eq!(
+            Some(PathBuf::from("/data/rlox/history")),
+            history_path(Some("/data".into()), Some("/home/lox".into()))
+        );
+        assert_eq!(
+            Some(PathBuf::from("/home/lox/.local/share/rlox/history")),
+            history_path(None, Some("/home/lox".into()))
+        );
+        // A relative XDG_DATA_HOME is ignored
+        assert_eq!(
+            Some(PathBuf::from("/home/lox/.local/share/rlox/history")),
+            history_path(Some("data".into()), Some("/home/lox".into()))
+        );
+        assert_eq!(None, history_path(None, None));
     }
 }
@@ -0,0 +1,427 @@
+use crate::lexer::Token;
+use crate::parser::{ClassDecl, Expr, ExprId, FunctionDecl, Stmt, StmtVisitor, Visitor};
+use std::collections::HashMap;
+use std::fmt;
+
+// A semantic error found before the program runs
+#[derive(Debug, PartialEq)]
+pub enum ResolveErrorKind {
+    ReadInOwnInitializer,
+    AlreadyDeclared,
+    TopLevelReturn,
+    ReturnFromInitializer,
+    ThisOutsideClass,
+    SuperOutsideClass,
+    SuperWithoutSuperclass,
+    InheritFromSelf,
+}
+
+impl fmt::Display for ResolveErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            ResolveErrorKind::ReadInOwnInitializer => {
+                "Can't read local variable in its own initializer."
+            }
+            ResolveErrorKind::AlreadyDeclared => "Already a variable with this name in this scope.",
+            ResolveErrorKind::TopLevelReturn => "Can't return from top-level code.",
+            ResolveErrorKind::ReturnFromInitializer => "Can't return a value from an initializer.",
+            ResolveErrorKind::ThisOutsideClass => "Can't use 'this' outside of a class.",
+            ResolveErrorKind::SuperOutsideClass => "Can't use 'super' outside of a class.",
+            ResolveErrorKind::SuperWithoutSuperclass => {
+                "Can't use 'super' in a class with no superclass."
+            }
+            ResolveErrorKind::InheritFromSelf => "A class can't inherit from itself.",
+        };
+
+        write!(f, "{}", msg)
+    }
+}
+
+// A semantic error along with the token where it was found
+#[derive(Debug)]
+pub struct ResolveError {
+    pub token: Token,
+    pub kind: ResolveErrorKind,
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+// How many scopes out each resolved local variable reference is
+pub type Locals = HashMap<ExprId, usize>;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Initializer,
+    Method,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+// A static pass that binds every local variable reference to the scope that declares it
+pub struct Resolver {
+    // Each scope maps a name to whether its initializer has finished
+    scopes: Vec<HashMap<String, bool>>,
+    locals: Locals,
+    current_function: FunctionType,
+    current_class: ClassType,
+    errors: Vec<ResolveError>,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Resolver {
+            scopes: Vec::new(),
+            locals: HashMap::new(),
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+            errors: Vec::new(),
+        }
+    }
+
+    // Resolve a whole program, returning the scope distance of every local variable
+    // reference, or every error found. Anything not in the map is a global.
+    pub fn resolve(mut self, stmts: &[Stmt]) -> Result<Locals, Vec<ResolveError>> {
+        self.resolve_stmts(stmts);
+        self.finish()
+    }
+
+    // Resolve a lone expression, as typed into the REPL
+    pub fn resolve_expr(mut self, expr: &Expr) -> Result<Locals, Vec<ResolveError>> {
+        self.visit_expr(expr);
+        self.finish()
+    }
+
+    fn finish(self) -> Result<Locals, Vec<ResolveError>> {
+        if self.errors.is_empty() {
+            Ok(self.locals)
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    fn resolve_stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.visit_stmt(stmt);
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn error(&mut self, token: &Token, kind: ResolveErrorKind) {
+        self.errors.push(ResolveError {
+            token: token.clone(),
+            kind,
+        });
+    }
+
+    // Add a name to the innermost scope, marking it as not yet initialized
+    fn declare(&mut self, name: &Token) {
+        let key = name.str_val().expect("Expected identifier");
+
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+
+        if scope.insert(key, false).is_some() {
+            self.error(name, ResolveErrorKind::AlreadyDeclared);
+        }
+    }
+
+    // Mark a name in the innermost scope as ready for use
+    fn define(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), true);
+        }
+    }
+
+    // Record how many scopes out the variable is declared, if it is a local at all
+    fn resolve_local(&mut self, id: ExprId, name: &str) {
+        if let Some(depth) = self
+            .scopes
+            .iter()
+            .rev()
+            .position(|scope| scope.contains_key(name))
+        {
+            self.locals.insert(id, depth);
+        }
+    }
+
+    fn resolve_function(&mut self, decl: &FunctionDecl, function_type: FunctionType) {
+        let enclosing_function = self.current_function;
+        self.current_function = function_type;
+
+        self.begin_scope();
+        for param in &decl.params {
+            self.declare(param);
+            self.define(&param.str_val().expect("Expected identifier"));
+        }
+        self.resolve_stmts(&decl.body);
+        self.end_scope();
+
+        self.current_function = enclosing_function;
+    }
+
+    fn resolve_class(&mut self, decl: &ClassDecl) {
+        let enclosing_class = self.current_class;
+        self.current_class = ClassType::Class;
+
+        let name = decl.name.str_val().expect("Expected identifier");
+        self.declare(&decl.name);
+        self.define(&name);
+
+        if let Some(ref superclass) = decl.superclass {
+            if let Expr::Variable(ref superclass_name, _) = superclass {
+                if superclass_name.str_val().as_deref() == Some(name.as_str()) {
+                    self.error(superclass_name, ResolveErrorKind::InheritFromSelf);
+                }
+            }
+
+            self.current_class = ClassType::Subclass;
+            self.visit_expr(superclass);
+
+            self.begin_scope();
+            self.define("super");
+        }
+
+        self.begin_scope();
+        self.define("this");
+
+        for method in &decl.methods {
+            let function_type = if method.name.str_val().as_deref() == Some("init") {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+            self.resolve_function(method, function_type);
+        }
+
+        self.end_scope();
+
+        if decl.superclass.is_some() {
+            self.end_scope();
+        }
+
+        self.current_class = enclosing_class;
+    }
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Visitor<()> for Resolver {
+    fn visit_expr(&mut self, expr: &Expr) {
+        match *expr {
+            Expr::Assign(ref name, ref value, id) => {
+                self.visit_expr(value);
+                self.resolve_local(id, &name.str_val().expect("Expected identifier"));
+            }
+            Expr::Bin(ref left, _, ref right) | Expr::Logical(ref left, _, ref right) => {
+                self.visit_expr(left);
+                self.visit_expr(right);
+            }
+            Expr::Call(ref callee, _, ref args) => {
+                self.visit_expr(callee);
+                for arg in args {
+                    self.visit_expr(arg);
+                }
+            }
+            Expr::Get(ref object, _) => self.visit_expr(object),
+            Expr::Grouping(ref expr) | Expr::Unary(_, ref expr) => self.visit_expr(expr),
+            Expr::Lit(..) => {}
+            Expr::Set(ref object, _, ref value) => {
+                self.visit_expr(value);
+                self.visit_expr(object);
+            }
+            Expr::Super(ref keyword, _, id) => {
+                match self.current_class {
+                    ClassType::None => self.error(keyword, ResolveErrorKind::SuperOutsideClass),
+                    ClassType::Class => {
+                        self.error(keyword, ResolveErrorKind::SuperWithoutSuperclass)
+                    }
+                    ClassType::Subclass => {}
+                }
+
+                self.resolve_local(id, "super");
+            }
+            Expr::This(ref keyword, id) => {
+                if self.current_class == ClassType::None {
+                    self.error(keyword, ResolveErrorKind::ThisOutsideClass);
+                    return;
+                }
+
+                self.resolve_local(id, "this");
+            }
+            Expr::Variable(ref name, id) => {
+                let key = name.str_val().expect("Expected identifier");
+
+                let declared_uninitialized = self
+                    .scopes
+                    .last()
+                    .is_some_and(|scope| scope.get(&key) == Some(&false));
+                if declared_uninitialized {
+                    self.error(name, ResolveErrorKind::ReadInOwnInitializer);
+                }
+
+                self.resolve_local(id, &key);
+            }
+        }
+    }
+}
+
+impl StmtVisitor<()> for Resolver {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match *stmt {
+            Stmt::Block(ref stmts) => {
+                self.begin_scope();
+                self.resolve_stmts(stmts);
+                self.end_scope();
+            }
+            Stmt::Class(ref decl) => self.resolve_class(decl),
+            Stmt::Expression(ref expr) | Stmt::Print(ref expr) => self.visit_expr(expr),
+            Stmt::Function(ref decl) => {
+                // Define the name eagerly so the function can refer to itself
+                self.declare(&decl.name);
+                self.define(&decl.name.str_val().expect("Expected identifier"));
+                self.resolve_function(decl, FunctionType::Function);
+            }
+            Stmt::If(ref condition, ref then_branch, ref else_branch) => {
+                self.visit_expr(condition);
+                self.visit_stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.visit_stmt(else_branch);
+                }
+            }
+            Stmt::Return(ref keyword, ref value) => {
+                if self.current_function == FunctionType::None {
+                    self.error(keyword, ResolveErrorKind::TopLevelReturn);
+                }
+
+                if let Some(value) = value {
+                    if self.current_function == FunctionType::Initializer {
+                        self.error(keyword, ResolveErrorKind::ReturnFromInitializer);
+                    }
+
+                    self.visit_expr(value);
+                }
+            }
+            Stmt::Var(ref name, ref initializer) => {
+                self.declare(name);
+                if let Some(initializer) = initializer {
+                    self.visit_expr(initializer);
+                }
+                self.define(&name.str_val().expect("Expected identifier"));
+            }
+            Stmt::While(ref condition, ref body) => {
+                self.visit_expr(condition);
+                self.visit_stmt(body);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_resolver {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn resolve_str(src: &str) -> Result<Locals, Vec<ResolveError>> {
+        let tokens = Lexer::new(src)
+            .lex_tokens()
+            .into_iter()
+            .map(|tok| tok.unwrap())
+            .collect();
+        let stmts = Parser::new(tokens).parse_tokens().unwrap();
+
+        Resolver::new().resolve(&stmts)
+    }
+
+    fn error_kinds(src: &str) -> Vec<ResolveErrorKind> {
+        resolve_str(src)
+            .expect_err("Expected resolve errors")
+            .into_iter()
+            .map(|err| err.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_scope_depths() {
+        // 'a' is a global, 'b' is one scope out from its use and 'c' is in the same scope
+        let locals = resolve_str("var a = 1; { var b = 2; { var c = a + b; print c; } }").unwrap();
+
+        let mut depths: Vec<usize> = locals.values().copied().collect();
+        depths.sort();
+        assert_eq!(vec![0, 1], depths);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            vec![ResolveErrorKind::ReadInOwnInitializer],
+            error_kinds("{ var a = a; }")
+        );
+        assert_eq!(
+            vec![ResolveErrorKind::AlreadyDeclared],
+            error_kinds("fun f(a) { var a; }")
+        );
+        assert_eq!(
+            vec![ResolveErrorKind::TopLevelReturn],
+            error_kinds("return 1;")
+        );
+        assert_eq!(
+            vec![ResolveErrorKind::ThisOutsideClass],
+            error_kinds("fun f() { return this; }")
+        );
+        assert_eq!(
+            vec![ResolveErrorKind::ReturnFromInitializer],
+            error_kinds("class A { init() { return 1; } }")
+        );
+        assert_eq!(
+            vec![ResolveErrorKind::SuperWithoutSuperclass],
+            error_kinds("class A { f() { super.f(); } }")
+        );
+        assert_eq!(
+            vec![ResolveErrorKind::SuperOutsideClass],
+            error_kinds("super.f();")
+        );
+        assert_eq!(
+            vec![ResolveErrorKind::InheritFromSelf],
+            error_kinds("class A < A {}")
+        );
+
+        // Globals may be redeclared and an initializer may return early without a value
+        assert!(resolve_str("var a; var a = a; class A { init() { return; } }").is_ok());
+    }
+
+    #[test]
+    fn test_errors_are_collected() {
+        assert_eq!(
+            vec![
+                ResolveErrorKind::TopLevelReturn,
+                ResolveErrorKind::AlreadyDeclared,
+                ResolveErrorKind::ThisOutsideClass,
+            ],
+            error_kinds("return; { var a; var a; } print this;")
+        );
+    }
+}
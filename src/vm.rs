@@ -0,0 +1,969 @@
+use crate::chunk::OpCode;
+use crate::compiler;
+use crate::disassembler::disassemble_instruction;
+use crate::heap::{
+    BoundMethod, Class, Closure, GcStats, Heap, Instance, Native, Obj, ObjRef, Upvalue, Value,
+};
+use crate::interpreter::{self, LoxError, RuntimeError, RuntimeErrorKind};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// How deep calls can nest before the program is assumed to recurse forever
+const FRAMES_MAX: usize = 1024;
+
+// A running call: the closure being run, where it is up to, and where its slots start on the
+// stack. Slot zero is the callee itself, or the receiver for methods.
+struct CallFrame {
+    closure: ObjRef,
+    // The closure's function, to save looking it up for every instruction
+    function: ObjRef,
+    ip: usize,
+    slots: usize,
+}
+
+// A stack-based virtual machine that runs bytecode compiled from Lox, as an alternative to
+// the tree-walking interpreter. Globals persist between runs.
+pub struct Vm {
+    heap: Heap,
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<ObjRef, Value>,
+    // Upvalues still pointing into the stack, which must be closed when their slots are popped
+    open_upvalues: Vec<ObjRef>,
+    init_string: ObjRef,
+    // Whether to print the stack and each instruction to stderr as it runs
+    trace: bool,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        let mut heap = Heap::new();
+        let init_string = heap.intern("init");
+        let mut vm = Vm {
+            heap,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals: HashMap::new(),
+            open_upvalues: Vec::new(),
+            init_string,
+            trace: false,
+        };
+
+        vm.define_native(Native {
+            name: "clock",
+            arity: 0,
+            function: clock,
+        });
+        vm.define_native(Native {
+            name: "gcStats",
+            arity: 0,
+            function: gc_stats,
+        });
+        vm
+    }
+
+    fn define_native(&mut self, native: Native) {
+        let name = self.heap.intern(native.name);
+        let native = self.heap.alloc(Obj::Native(native));
+        self.globals.insert(name, Value::Obj(native));
+    }
+
+    // Compile and run a piece of Lox source code
+    pub fn run(&mut self, src: &str) -> Result<(), LoxError> {
+        let (stmts, _) = interpreter::compile(src).map_err(LoxError::Compile)?;
+        let script = compiler::compile(&stmts, &mut self.heap).map_err(LoxError::Compile)?;
+        self.run_script(script)
+    }
+
+    // Run a script already compiled onto this VM's heap
+    pub fn run_script(&mut self, script: ObjRef) -> Result<(), LoxError> {
+        self.interpret(script).map_err(|err| {
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+            LoxError::Runtime(err)
+        })
+    }
+
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    pub fn heap_mut(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    // The value of a global variable, if it is defined
+    pub fn global(&mut self, name: &str) -> Option<Value> {
+        let name = self.heap.intern(name);
+        self.globals.get(&name).copied()
+    }
+
+    pub fn set_gc_stress(&mut self, stress: bool) {
+        self.heap.set_stress(stress);
+    }
+
+    // Whether to print a line to stderr after each garbage collection
+    pub fn set_gc_log(&mut self, log: bool) {
+        self.heap.set_log(log);
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.stats()
+    }
+
+    // Allocate an object, first collecting garbage if it's time to. Anything the caller still
+    // needs has to be reachable from the roots, such as by being on the stack.
+    fn alloc(&mut self, obj: Obj) -> ObjRef {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.alloc(obj)
+    }
+
+    fn intern(&mut self, s: &str) -> ObjRef {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.intern(s)
+    }
+
+    fn collect_garbage(&mut self) {
+        let Vm {
+            heap,
+            stack,
+            frames,
+            globals,
+            open_upvalues,
+            init_string,
+            ..
+        } = self;
+
+        heap.collect(|heap| {
+            for &value in stack.iter() {
+                heap.mark_value(value);
+            }
+            // A frame's closure isn't always in its first slot, which holds the receiver of
+            // methods
+            for frame in frames.iter() {
+                heap.mark_object(frame.closure);
+            }
+            for &upvalue in open_upvalues.iter() {
+                heap.mark_object(upvalue);
+            }
+            for (&name, &value) in globals.iter() {
+                heap.mark_object(name);
+                heap.mark_value(value);
+            }
+            heap.mark_object(*init_string);
+        });
+    }
+
+    fn interpret(&mut self, script: ObjRef) -> Result<(), RuntimeError> {
+        // The script is kept on the stack until its closure is made, so it can't be collected
+        self.push(Value::Obj(script));
+        let closure = self.alloc(Obj::Closure(Closure {
+            function: script,
+            upvalues: Vec::new(),
+        }));
+        self.pop();
+        self.push(Value::Obj(closure));
+        self.call(closure, 0)?;
+        self.execute()
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frames.last_mut().unwrap();
+        let byte = self.heap.function(frame.function).chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_short(&mut self) -> usize {
+        let high = self.read_byte();
+        let low = self.read_byte();
+        u16::from_be_bytes([high, low]) as usize
+    }
+
+    fn read_constant(&mut self) -> Value {
+        let index = self.read_byte() as usize;
+        self.heap.function(self.frame().function).chunk.constants[index]
+    }
+
+    // Constants read as names are always interned strings
+    fn read_string(&mut self) -> ObjRef {
+        match self.read_constant() {
+            Value::Obj(string) => string,
+            other => unreachable!("Expected a name constant, found {:?}", other),
+        }
+    }
+
+    fn push(&mut self, value: Value) {
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack
+            .pop()
+            .expect("The compiler keeps the stack balanced")
+    }
+
+    fn peek(&self, distance: usize) -> Value {
+        self.stack[self.stack.len() - 1 - distance]
+    }
+
+    // Raise an error at the instruction being run
+    fn error(&self, kind: RuntimeErrorKind) -> RuntimeError {
+        let frame = self.frame();
+        let span = self
+            .heap
+            .function(frame.function)
+            .chunk
+            .span_at(frame.ip - 1);
+        RuntimeError::new(kind, span)
+    }
+
+    fn name_error(&self, kind: fn(String) -> RuntimeErrorKind, name: ObjRef) -> RuntimeError {
+        self.error(kind(self.heap.string(name).to_string()))
+    }
+
+    // Pop the operands of an arithmetic or comparison instruction, which must be numbers
+    fn pop_numbers(&mut self) -> Result<(f64, f64), RuntimeError> {
+        match (self.peek(1), self.peek(0)) {
+            (Value::Number(a), Value::Number(b)) => {
+                self.stack.truncate(self.stack.len() - 2);
+                Ok((a, b))
+            }
+            _ => Err(self.error(RuntimeErrorKind::OperandsMustBeNumbers)),
+        }
+    }
+
+    // Print the stack followed by the instruction about to run
+    fn trace_instruction(&self) {
+        let stack: String = self
+            .stack
+            .iter()
+            .map(|&value| format!("[ {} ]", self.heap.format(value)))
+            .collect();
+        let frame = self.frame();
+        let chunk = &self.heap.function(frame.function).chunk;
+        let (instruction, _) = disassemble_instruction(chunk, frame.ip, &self.heap);
+        eprintln!("          {}\n{}", stack, instruction);
+    }
+
+    fn execute(&mut self) -> Result<(), RuntimeError> {
+        loop {
+            if self.trace {
+                self.trace_instruction();
+            }
+
+            let byte = self.read_byte();
+            let op = OpCode::try_from(byte)
+                .unwrap_or_else(|byte| unreachable!("Unknown opcode {}", byte));
+
+            match op {
+                OpCode::Constant => {
+                    let constant = self.read_constant();
+                    self.push(constant);
+                }
+                OpCode::Nil => self.push(Value::Nil),
+                OpCode::True => self.push(Value::Bool(true)),
+                OpCode::False => self.push(Value::Bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.push(self.stack[slot]);
+                }
+                OpCode::SetLocal => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.stack[slot] = self.peek(0);
+                }
+                OpCode::GetGlobal => {
+                    let name = self.read_string();
+                    match self.globals.get(&name) {
+                        Some(&value) => self.push(value),
+                        None => {
+                            return Err(self.name_error(RuntimeErrorKind::UndefinedVariable, name))
+                        }
+                    }
+                }
+                OpCode::DefineGlobal => {
+                    let name = self.read_string();
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                }
+                OpCode::SetGlobal => {
+                    let name = self.read_string();
+                    let value = self.peek(0);
+                    match self.globals.get_mut(&name) {
+                        Some(global) => *global = value,
+                        None => {
+                            return Err(self.name_error(RuntimeErrorKind::UndefinedVariable, name))
+                        }
+                    }
+                }
+                OpCode::GetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.heap.closure(self.frame().closure).upvalues[index];
+                    let value = match self.heap.get(upvalue) {
+                        Obj::Upvalue(Upvalue::Open(slot)) => self.stack[*slot],
+                        Obj::Upvalue(Upvalue::Closed(value)) => *value,
+                        other => unreachable!("Expected an upvalue, found {:?}", other),
+                    };
+                    self.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.heap.closure(self.frame().closure).upvalues[index];
+                    let value = self.peek(0);
+                    match self.heap.upvalue_mut(upvalue) {
+                        Upvalue::Open(slot) => {
+                            let slot = *slot;
+                            self.stack[slot] = value;
+                        }
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::GetProperty => {
+                    let name = self.read_string();
+                    let Some(instance) = self.instance(self.peek(0)) else {
+                        return Err(self.error(RuntimeErrorKind::OnlyInstancesHaveProperties));
+                    };
+
+                    // Fields shadow methods
+                    if let Some(&value) = instance.fields.get(&name) {
+                        self.pop();
+                        self.push(value);
+                    } else {
+                        let class = instance.class;
+                        self.bind_method(class, name)?;
+                    }
+                }
+                OpCode::SetProperty => {
+                    let name = self.read_string();
+                    let Value::Obj(instance) = self.peek(1) else {
+                        return Err(self.error(RuntimeErrorKind::OnlyInstancesHaveFields));
+                    };
+                    let value = self.peek(0);
+                    match self.heap.get_mut(instance) {
+                        Obj::Instance(instance) => instance.fields.insert(name, value),
+                        _ => return Err(self.error(RuntimeErrorKind::OnlyInstancesHaveFields)),
+                    };
+
+                    self.stack.truncate(self.stack.len() - 2);
+                    self.push(value);
+                }
+                OpCode::GetSuper => {
+                    let name = self.read_string();
+                    let Some(superclass) = self.class(self.peek(0)) else {
+                        return Err(self.error(RuntimeErrorKind::SuperclassMustBeClass));
+                    };
+                    self.pop();
+                    self.bind_method(superclass, name)?;
+                }
+                OpCode::Equal => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.push(Value::Bool(a == b));
+                }
+                OpCode::Greater => {
+                    let (a, b) = self.pop_numbers()?;
+                    self.push(Value::Bool(a > b));
+                }
+                OpCode::GreaterEqual => {
+                    let (a, b) = self.pop_numbers()?;
+                    self.push(Value::Bool(a >= b));
+                }
+                OpCode::Less => {
+                    let (a, b) = self.pop_numbers()?;
+                    self.push(Value::Bool(a < b));
+                }
+                OpCode::LessEqual => {
+                    let (a, b) = self.pop_numbers()?;
+                    self.push(Value::Bool(a <= b));
+                }
+                OpCode::Add => match (self.peek(1), self.peek(0)) {
+                    (Value::Number(a), Value::Number(b)) => {
+                        self.stack.truncate(self.stack.len() - 2);
+                        self.push(Value::Number(a + b));
+                    }
+                    (Value::Obj(a), Value::Obj(b))
+                        if matches!(self.heap.get(a), Obj::String(_))
+                            && matches!(self.heap.get(b), Obj::String(_)) =>
+                    {
+                        let concatenated = [self.heap.string(a), self.heap.string(b)].concat();
+                        let string = self.intern(&concatenated);
+                        self.stack.truncate(self.stack.len() - 2);
+                        self.push(Value::Obj(string));
+                    }
+                    _ => return Err(self.error(RuntimeErrorKind::OperandsMustBeNumbersOrStrings)),
+                },
+                OpCode::Subtract => {
+                    let (a, b) = self.pop_numbers()?;
+                    self.push(Value::Number(a - b));
+                }
+                OpCode::Multiply => {
+                    let (a, b) = self.pop_numbers()?;
+                    self.push(Value::Number(a * b));
+                }
+                OpCode::Divide => {
+                    let (a, b) = self.pop_numbers()?;
+                    self.push(Value::Number(a / b));
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.push(Value::Bool(!value.is_truthy()));
+                }
+                OpCode::Negate => match self.peek(0) {
+                    Value::Number(n) => {
+                        self.pop();
+                        self.push(Value::Number(-n));
+                    }
+                    _ => return Err(self.error(RuntimeErrorKind::OperandMustBeNumber)),
+                },
+                OpCode::Print => {
+                    let value = self.pop();
+                    println!("{}", self.heap.format(value));
+                }
+                OpCode::Jump => {
+                    let offset = self.read_short();
+                    self.frames.last_mut().unwrap().ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_short();
+                    if !self.peek(0).is_truthy() {
+                        self.frames.last_mut().unwrap().ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_short();
+                    self.frames.last_mut().unwrap().ip -= offset;
+                }
+                OpCode::Call => {
+                    let argc = self.read_byte() as usize;
+                    self.call_value(self.peek(argc), argc)?;
+                }
+                OpCode::Invoke => {
+                    let name = self.read_string();
+                    let argc = self.read_byte() as usize;
+                    self.invoke(name, argc)?;
+                }
+                OpCode::SuperInvoke => {
+                    let name = self.read_string();
+                    let argc = self.read_byte() as usize;
+                    let Some(superclass) = self.class(self.peek(0)) else {
+                        return Err(self.error(RuntimeErrorKind::SuperclassMustBeClass));
+                    };
+                    self.pop();
+                    self.invoke_from_class(superclass, name, argc)?;
+                }
+                OpCode::Closure => {
+                    let Value::Obj(function) = self.read_constant() else {
+                        unreachable!("Closures are only made from function constants")
+                    };
+
+                    // The closure goes on the stack before capturing its upvalues, so that
+                    // those already captured survive a collection while capturing the rest
+                    let closure = self.alloc(Obj::Closure(Closure {
+                        function,
+                        upvalues: Vec::new(),
+                    }));
+                    self.push(Value::Obj(closure));
+
+                    let upvalue_count = self.heap.function(function).upvalue_count;
+                    for _ in 0..upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_byte() as usize;
+                        let upvalue = if is_local {
+                            self.capture_upvalue(self.frame().slots + index)
+                        } else {
+                            self.heap.closure(self.frame().closure).upvalues[index]
+                        };
+                        self.heap.closure_mut(closure).upvalues.push(upvalue);
+                    }
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    self.close_upvalues(frame.slots);
+                    self.stack.truncate(frame.slots);
+
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.push(result);
+                }
+                OpCode::Class => {
+                    let name = self.read_string();
+                    let class = self.alloc(Obj::Class(Class {
+                        name,
+                        methods: HashMap::new(),
+                    }));
+                    self.push(Value::Obj(class));
+                }
+                OpCode::Inherit => {
+                    let Some(superclass) = self.class(self.peek(1)) else {
+                        return Err(self.error(RuntimeErrorKind::SuperclassMustBeClass));
+                    };
+                    // Only a hand-made script can inherit into something else
+                    let Some(subclass) = self.class(self.peek(0)) else {
+                        return Err(self.error(RuntimeErrorKind::MalformedBytecode));
+                    };
+
+                    // Copying the methods down now means calls never have to walk up the chain
+                    let methods = self.heap.class(superclass).methods.clone();
+                    self.heap.class_mut(subclass).methods.extend(methods);
+                    self.pop();
+                }
+                OpCode::Method => {
+                    let name = self.read_string();
+                    let (Some(method), Some(class)) =
+                        (self.closure(self.peek(0)), self.class(self.peek(1)))
+                    else {
+                        return Err(self.error(RuntimeErrorKind::MalformedBytecode));
+                    };
+                    self.heap.class_mut(class).methods.insert(name, method);
+                    self.pop();
+                }
+            }
+        }
+    }
+
+    fn instance(&self, value: Value) -> Option<&Instance> {
+        match value {
+            Value::Obj(obj) => match self.heap.get(obj) {
+                Obj::Instance(instance) => Some(instance),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn class(&self, value: Value) -> Option<ObjRef> {
+        match value {
+            Value::Obj(obj) if matches!(self.heap.get(obj), Obj::Class(_)) => Some(obj),
+            _ => None,
+        }
+    }
+
+    fn closure(&self, value: Value) -> Option<ObjRef> {
+        match value {
+            Value::Obj(obj) if matches!(self.heap.get(obj), Obj::Closure(_)) => Some(obj),
+            _ => None,
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, argc: usize) -> Result<(), RuntimeError> {
+        let Value::Obj(callee) = callee else {
+            return Err(self.error(RuntimeErrorKind::NotCallable));
+        };
+
+        match self.heap.get(callee) {
+            Obj::Closure(_) => self.call(callee, argc),
+            Obj::Native(native) => {
+                if argc != native.arity {
+                    return Err(self.error(RuntimeErrorKind::WrongArity {
+                        expected: native.arity,
+                        got: argc,
+                    }));
+                }
+
+                let function = native.function;
+                let args = self.stack.len() - argc;
+                let result = function(&mut self.heap, &self.stack[args..]);
+                self.stack.truncate(args - 1);
+                self.push(result);
+                Ok(())
+            }
+            Obj::Class(class) => {
+                let initializer = class.methods.get(&self.init_string).copied();
+                let instance = self.alloc(Obj::Instance(Instance {
+                    class: callee,
+                    fields: HashMap::new(),
+                }));
+                let receiver = self.stack.len() - argc - 1;
+                self.stack[receiver] = Value::Obj(instance);
+
+                match initializer {
+                    Some(initializer) => self.call(initializer, argc),
+                    None if argc != 0 => Err(self.error(RuntimeErrorKind::WrongArity {
+                        expected: 0,
+                        got: argc,
+                    })),
+                    None => Ok(()),
+                }
+            }
+            Obj::BoundMethod(bound) => {
+                let method = bound.method;
+                let receiver = self.stack.len() - argc - 1;
+                self.stack[receiver] = bound.receiver;
+                self.call(method, argc)
+            }
+            _ => Err(self.error(RuntimeErrorKind::NotCallable)),
+        }
+    }
+
+    fn call(&mut self, closure: ObjRef, argc: usize) -> Result<(), RuntimeError> {
+        let function = self.heap.closure(closure).function;
+        let arity = self.heap.function(function).arity;
+        if argc != arity {
+            return Err(self.error(RuntimeErrorKind::WrongArity {
+                expected: arity,
+                got: argc,
+            }));
+        }
+
+        if self.frames.len() == FRAMES_MAX {
+            return Err(self.error(RuntimeErrorKind::StackOverflow));
+        }
+
+        self.frames.push(CallFrame {
+            closure,
+            function,
+            ip: 0,
+            slots: self.stack.len() - argc - 1,
+        });
+        Ok(())
+    }
+
+    // Call a method by name on the receiver below the arguments
+    fn invoke(&mut self, name: ObjRef, argc: usize) -> Result<(), RuntimeError> {
+        let Some(instance) = self.instance(self.peek(argc)) else {
+            return Err(self.error(RuntimeErrorKind::OnlyInstancesHaveProperties));
+        };
+
+        // A field holding a function is called like any other value
+        if let Some(&field) = instance.fields.get(&name) {
+            let receiver = self.stack.len() - argc - 1;
+            self.stack[receiver] = field;
+            return self.call_value(field, argc);
+        }
+
+        let class = instance.class;
+        self.invoke_from_class(class, name, argc)
+    }
+
+    fn invoke_from_class(
+        &mut self,
+        class: ObjRef,
+        name: ObjRef,
+        argc: usize,
+    ) -> Result<(), RuntimeError> {
+        match self.heap.class(class).methods.get(&name) {
+            Some(&method) => self.call(method, argc),
+            None => Err(self.name_error(RuntimeErrorKind::UndefinedProperty, name)),
+        }
+    }
+
+    // Replace the instance on top of the stack with one of its class's methods bound to it
+    fn bind_method(&mut self, class: ObjRef, name: ObjRef) -> Result<(), RuntimeError> {
+        let Some(&method) = self.heap.class(class).methods.get(&name) else {
+            return Err(self.name_error(RuntimeErrorKind::UndefinedProperty, name));
+        };
+
+        // The receiver stays on the stack while the bound method is allocated
+        let receiver = self.peek(0);
+        let bound = self.alloc(Obj::BoundMethod(BoundMethod { receiver, method }));
+        self.pop();
+        self.push(Value::Obj(bound));
+        Ok(())
+    }
+
+    // Return the upvalue for a stack slot, sharing it with any other closure that captured it
+    fn capture_upvalue(&mut self, slot: usize) -> ObjRef {
+        let existing = self.open_upvalues.iter().copied().find(|&upvalue| {
+            matches!(self.heap.get(upvalue), Obj::Upvalue(Upvalue::Open(open)) if *open == slot)
+        });
+        if let Some(upvalue) = existing {
+            return upvalue;
+        }
+
+        let upvalue = self.alloc(Obj::Upvalue(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue);
+        upvalue
+    }
+
+    // Move the values of every upvalue pointing at or above a stack slot onto the heap
+    fn close_upvalues(&mut self, last: usize) {
+        let heap = &mut self.heap;
+        let stack = &self.stack;
+        self.open_upvalues.retain(|&upvalue| {
+            let upvalue = heap.upvalue_mut(upvalue);
+            match *upvalue {
+                Upvalue::Open(slot) if slot >= last => {
+                    *upvalue = Upvalue::Closed(stack[slot]);
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+}
+
+// Return the number of seconds since the Unix epoch
+fn clock(_heap: &mut Heap, _args: &[Value]) -> Value {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    Value::Number(now.as_secs_f64())
+}
+
+// Return a GcStats instance describing the heap, with a LiveObjects instance counting its
+// objects by kind. The numbers are taken before either instance is allocated.
+fn gc_stats(heap: &mut Heap, _args: &[Value]) -> Value {
+    let stats = heap.stats();
+    let counts = stats.live_objects;
+    let number = |n: usize| Value::Number(n as f64);
+
+    let live_objects = new_instance(
+        heap,
+        "LiveObjects",
+        &[
+            ("total", number(counts.total())),
+            ("strings", number(counts.strings)),
+            ("functions", number(counts.functions)),
+            ("natives", number(counts.natives)),
+            ("closures", number(counts.closures)),
+            ("upvalues", number(counts.upvalues)),
+            ("classes", number(counts.classes)),
+            ("instances", number(counts.instances)),
+            ("boundMethods", number(counts.bound_methods)),
+        ],
+    );
+    new_instance(
+        heap,
+        "GcStats",
+        &[
+            ("bytesAllocated", number(stats.bytes_allocated)),
+            ("liveObjects", live_objects),
+            ("collections", number(stats.collections)),
+            (
+                "totalPauseMs",
+                Value::Number(stats.total_pause.as_secs_f64() * 1000.0),
+            ),
+            ("peakBytes", number(stats.peak_bytes)),
+        ],
+    )
+}
+
+// Make an instance of a new class with no methods, for natives to return
+fn new_instance(heap: &mut Heap, class_name: &str, fields: &[(&str, Value)]) -> Value {
+    let name = heap.intern(class_name);
+    let class = heap.alloc(Obj::Class(Class {
+        name,
+        methods: HashMap::new(),
+    }));
+    let fields = fields
+        .iter()
+        .map(|&(name, value)| (heap.intern(name), value))
+        .collect();
+
+    Value::Obj(heap.alloc(Obj::Instance(Instance { class, fields })))
+}
+
+#[cfg(test)]
+mod test_vm {
+    use super::*;
+    use std::time::Duration;
+
+    fn global(vm: &mut Vm, name: &str) -> String {
+        let value = vm.global(name).unwrap();
+        vm.heap().format(value)
+    }
+
+    #[test]
+    fn test_run() {
+        let mut vm = Vm::new();
+        vm.run("var a = 1 + 2 * 3; var b = \"lo\" + \"x\"; var c = !(a > 5);")
+            .unwrap();
+
+        assert_eq!("7", global(&mut vm, "a"));
+        assert_eq!("lox", global(&mut vm, "b"));
+        assert_eq!("false", global(&mut vm, "c"));
+        // Concatenated strings are interned like any other
+        assert_eq!(vm.global("b"), Some(Value::Obj(vm.heap.intern("lox"))));
+    }
+
+    #[test]
+    fn test_closures() {
+        let mut vm = Vm::new();
+        let src = "
+            fun counter() {
+                var count = 0;
+                fun increment() { count = count + 1; return count; }
+                return increment;
+            }
+            var next = counter();
+            next();
+            var a = next();
+            var other = counter();
+            var b = other();
+        ";
+        vm.run(src).unwrap();
+
+        assert_eq!("2", global(&mut vm, "a"));
+        assert_eq!("1", global(&mut vm, "b"));
+    }
+
+    #[test]
+    fn test_classes() {
+        let mut vm = Vm::new();
+        let src = "
+            class A {
+                init(x) { this.x = x; }
+                get() { return this.x; }
+            }
+            class B < A {
+                init(x) { super.init(x * 2); }
+                get() { return super.get() + 1; }
+            }
+            var b = B(3);
+            var value = b.get();
+            var method = b.get;
+            var bound = method();
+        ";
+        vm.run(src).unwrap();
+
+        assert_eq!("B instance", global(&mut vm, "b"));
+        assert_eq!("7", global(&mut vm, "value"));
+        assert_eq!("<fn get>", global(&mut vm, "method"));
+        assert_eq!("7", global(&mut vm, "bound"));
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let mut vm = Vm::new();
+        let err = |vm: &mut Vm, src: &str| match vm.run(src) {
+            Err(LoxError::Runtime(err)) => (err.kind, err.span.line),
+            other => panic!("Expected a runtime error, got {:?}", other),
+        };
+
+        assert_eq!(
+            (RuntimeErrorKind::OperandsMustBeNumbers, 2),
+            err(&mut vm, "var a = 1;\nprint a - \"b\";")
+        );
+        assert_eq!(
+            (
+                RuntimeErrorKind::UndefinedVariable("missing".to_string()),
+                1
+            ),
+            err(&mut vm, "missing = 1;")
+        );
+        assert_eq!(
+            (
+                RuntimeErrorKind::WrongArity {
+                    expected: 1,
+                    got: 0
+                },
+                1
+            ),
+            err(&mut vm, "fun f(a) {} f();")
+        );
+        assert_eq!(
+            (RuntimeErrorKind::StackOverflow, 1),
+            err(&mut vm, "fun f() { f(); } f();")
+        );
+
+        // The VM is left ready to run more code after an error
+        vm.run("var after = a + 1;").unwrap();
+        assert_eq!("2", global(&mut vm, "after"));
+    }
+
+    #[test]
+    fn test_gc_cycles() {
+        // Each node refers to itself and holds a closure that captures it
+        let src = |count: usize| {
+            format!(
+                "class Node {{
+                    init() {{
+                        this.me = this;
+                        fun get() {{ return this; }}
+                        this.get = get;
+                    }}
+                }}
+                var last;
+                for (var i = 0; i < {}; i = i + 1) {{ last = Node(); }}",
+                count
+            )
+        };
+        let live_after = |count: usize| {
+            let mut vm = Vm::new();
+            vm.set_gc_stress(true);
+            vm.run(&src(count)).unwrap();
+            vm.collect_garbage();
+            vm.heap.len()
+        };
+
+        // Only the last node survives, however many were made
+        assert_eq!(live_after(1), live_after(200));
+    }
+
+    #[test]
+    fn test_gc_stress() {
+        let mut vm = Vm::new();
+        vm.set_gc_stress(true);
+        let src = "
+            fun make(prefix) {
+                var parts = prefix;
+                fun add(part) { parts = parts + part; return parts; }
+                return add;
+            }
+            var add = make(\"a\");
+            for (var i = 0; i < 5; i = i + 1) { add(\"b\"); }
+            var result = add(\"c\");
+        ";
+        vm.run(src).unwrap();
+
+        assert_eq!("abbbbbc", global(&mut vm, "result"));
+    }
+
+    #[test]
+    fn test_gc_stats() {
+        let mut vm = Vm::new();
+        vm.set_gc_stress(true);
+        let src = "
+            class Node { init(next) { this.next = next; } }
+            var list;
+            for (var i = 0; i < 10; i = i + 1) { list = Node(list); }
+            var stats = gcStats();
+            var collections = stats.collections;
+            var instances = stats.liveObjects.instances;
+            var total = stats.liveObjects.total;
+            var peak = stats.peakBytes >= stats.bytesAllocated;
+        ";
+        vm.run(src).unwrap();
+
+        // The script's view matches the VM's, other than what gcStats() allocated itself
+        let stats = vm.gc_stats();
+        assert!(stats.collections > 0);
+        assert_eq!(
+            stats.collections.to_string(),
+            global(&mut vm, "collections")
+        );
+        assert_eq!("10", global(&mut vm, "instances"));
+        assert_eq!(12, stats.live_objects.instances);
+        assert_eq!("true", global(&mut vm, "peak"));
+        assert!(stats.total_pause > Duration::ZERO);
+    }
+}
@@ -0,0 +1,324 @@
+// Runs every .lox file in a corpus annotated in the format of the Crafting Interpreters
+// test suite through rlox, and checks its output and exit code against the annotations:
+//
+//   // expect: <line>                 a line printed to stdout
+//   // expect runtime error: <msg>    a runtime error on this line; exits with 70
+//   // Error at 'x': <msg>            a compile error on this line; exits with 65
+//   // [line N] Error at 'x': <msg>   a compile error on another line; exits with 65
+//
+// Errors marked "[c line N]" are only reported by clox and are ignored, and files
+// containing "// nontest" are skipped.
+//
+// Every test is run on each backend, the tree-walking interpreter and the bytecode VM, and
+// again on the VM collecting garbage on every allocation.
+// The corpus is tests/conformance, or the directory in LOX_TEST_DIR, so that the upstream
+// suite can be dropped in. Pass rates are reported for each backend and subdirectory, which
+// upstream are the book's chapters. Any other arguments only run the files whose paths
+// contain them.
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{env, fs};
+
+// How long a single test may run before it is assumed to be stuck
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// The ways each test is run: a name for reports and the options given to rlox
+const BACKENDS: [(&str, &[&str]); 3] = [
+    ("tree", &["--backend=tree"]),
+    ("vm", &["--backend=vm"]),
+    ("vm-gc-stress", &["--backend=vm", "--gc-stress"]),
+];
+
+// What a test file's annotations say running it should do
+#[derive(Default)]
+struct Expectations {
+    output: Vec<String>,
+    // Compile errors, as rlox reports them
+    errors: Vec<String>,
+    // A runtime error message and the line it is raised on
+    runtime_error: Option<(String, usize)>,
+    exit_code: i32,
+}
+
+// What actually happened when a test file was run
+struct Outcome {
+    stdout: String,
+    stderr: String,
+    exit_code: Option<i32>,
+}
+
+fn main() {
+    let corpus = env::var_os("LOX_TEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance"));
+    // Flags are passed through by cargo test and can be ignored
+    let filters: Vec<String> = env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with('-'))
+        .collect();
+
+    let mut files = Vec::new();
+    find_lox_files(&corpus, &mut files);
+    files.sort();
+    files.retain(|file| {
+        let path = file.to_string_lossy();
+        filters.is_empty() || filters.iter().any(|filter| path.contains(filter.as_str()))
+    });
+
+    let mut failed = 0;
+    // Number of tests passed and run in each directory, for each backend
+    let mut results: Vec<BTreeMap<String, (usize, usize)>> = vec![BTreeMap::new(); BACKENDS.len()];
+
+    for file in &files {
+        let src = fs::read_to_string(file)
+            .unwrap_or_else(|e| panic!("Failed to read test {}: {}", file.display(), e));
+        let Some(expected) = parse_expectations(&src) else {
+            continue;
+        };
+
+        let suite = file
+            .parent()
+            .and_then(|dir| dir.strip_prefix(&corpus).ok())
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .filter(|dir| !dir.is_empty())
+            .unwrap_or_else(|| ".".to_string());
+
+        for ((backend, options), results) in BACKENDS.iter().zip(&mut results) {
+            let failures = check(&expected, &run(file, options));
+            let counts = results.entry(suite.clone()).or_default();
+            counts.1 += 1;
+
+            if failures.is_empty() {
+                counts.0 += 1;
+            } else {
+                failed += 1;
+                println!(
+                    "FAIL [{}] {}",
+                    backend,
+                    file.strip_prefix(&corpus).unwrap_or(file).display()
+                );
+                for failure in failures {
+                    println!("     {}", failure);
+                }
+            }
+        }
+    }
+
+    for ((backend, _), results) in BACKENDS.iter().zip(&results) {
+        println!("\n{}:", backend);
+        for (suite, (passed, total)) in results {
+            println!(
+                "{:<24} {:>4}/{:<4} {:>6.1}%",
+                suite,
+                passed,
+                total,
+                percent(*passed, *total)
+            );
+        }
+
+        let passed: usize = results.values().map(|(passed, _)| passed).sum();
+        let total: usize = results.values().map(|(_, total)| total).sum();
+        println!(
+            "{:<24} {:>4}/{:<4} {:>6.1}%",
+            "total",
+            passed,
+            total,
+            percent(passed, total)
+        );
+    }
+
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+// Recursively collect the .lox files under a directory
+fn find_lox_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("Failed to read test directory {}: {}", dir.display(), e));
+
+    for entry in entries {
+        let path = entry.expect("Failed to read directory entry").path();
+        if path.is_dir() {
+            find_lox_files(&path, files);
+        } else if path.extension().is_some_and(|ext| ext == "lox") {
+            files.push(path);
+        }
+    }
+}
+
+fn percent(passed: usize, total: usize) -> f64 {
+    if total == 0 {
+        return 100.0;
+    }
+
+    100.0 * passed as f64 / total as f64
+}
+
+// Read the annotations from a test file, or None if it isn't a test
+fn parse_expectations(src: &str) -> Option<Expectations> {
+    let mut expected = Expectations::default();
+
+    for (i, line) in src.lines().enumerate() {
+        let line_no = i + 1;
+
+        if line.contains("// nontest") {
+            return None;
+        }
+
+        if let Some(output) = after(line, "// expect: ") {
+            expected.output.push(output.to_string());
+        } else if let Some(error) = after(line, "// Error") {
+            expected
+                .errors
+                .push(format!("[line {}] Error{}", line_no, error));
+            expected.exit_code = 65;
+        } else if let Some(error) = after(line, "// [") {
+            if let Some((line_no, error)) = parse_error_line(error) {
+                expected
+                    .errors
+                    .push(format!("[line {}] {}", line_no, error));
+                expected.exit_code = 65;
+            }
+        } else if let Some(message) = after(line, "// expect runtime error: ") {
+            expected.runtime_error = Some((message.to_string(), line_no));
+            expected.exit_code = 70;
+        }
+    }
+
+    Some(expected)
+}
+
+// Return what follows the first occurrence of a marker in a line
+fn after<'a>(line: &'a str, marker: &str) -> Option<&'a str> {
+    line.find(marker).map(|start| &line[start + marker.len()..])
+}
+
+// Parse the rest of an error annotation with an explicit line, "line N] Error...",
+// skipping errors that only the C implementation reports
+fn parse_error_line(annotation: &str) -> Option<(usize, &str)> {
+    let annotation = annotation.strip_prefix("java ").unwrap_or(annotation);
+    let (line_no, error) = annotation.strip_prefix("line ")?.split_once("] ")?;
+
+    if !error.starts_with("Error") {
+        return None;
+    }
+
+    Some((line_no.parse().ok()?, error))
+}
+
+// Run a test file through rlox with some options, killing it if it takes too long
+fn run(file: &Path, options: &[&str]) -> Outcome {
+    // Errors are reported in the reference implementation's format to match the annotations
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .arg("--error-format=short")
+        .args(options)
+        .arg(file)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run rlox");
+
+    // Drain the pipes while waiting so that a chatty test can't block on a full pipe
+    let stdout = drain(child.stdout.take().unwrap());
+    let stderr = drain(child.stderr.take().unwrap());
+    let exit_code = wait(&mut child);
+
+    Outcome {
+        stdout: stdout.join().unwrap(),
+        stderr: stderr.join().unwrap(),
+        exit_code,
+    }
+}
+
+fn drain(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        pipe.read_to_end(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf).into_owned()
+    })
+}
+
+// Wait for a child to exit and return its exit code, or None if it timed out
+fn wait(child: &mut Child) -> Option<i32> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().expect("Failed to wait for rlox") {
+            return status.code();
+        }
+
+        if start.elapsed() > TIMEOUT {
+            child.kill().ok();
+            child.wait().ok();
+            return None;
+        }
+
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+// Compare what happened against what was expected, describing every difference
+fn check(expected: &Expectations, outcome: &Outcome) -> Vec<String> {
+    let mut failures = Vec::new();
+
+    match outcome.exit_code {
+        Some(code) if code == expected.exit_code => {}
+        Some(code) => failures.push(format!(
+            "Expected exit code {} but got {}.",
+            expected.exit_code, code
+        )),
+        None => {
+            failures.push(format!("Timed out after {} seconds.", TIMEOUT.as_secs()));
+            return failures;
+        }
+    }
+
+    let stderr: Vec<&str> = outcome.stderr.lines().collect();
+    if let Some((ref message, line_no)) = expected.runtime_error {
+        let trace = format!("[line {}]", line_no);
+        match stderr.as_slice() {
+            [actual, actual_trace, ..] if actual == message && *actual_trace == trace => {}
+            _ => failures.push(format!(
+                "Expected runtime error '{}' at {} but got {:?}.",
+                message, trace, stderr
+            )),
+        }
+    } else {
+        // Compile errors may be reported in any order
+        let mut actual = stderr.clone();
+        actual.sort_unstable();
+        let mut errors: Vec<&str> = expected.errors.iter().map(String::as_str).collect();
+        errors.sort_unstable();
+
+        for missing in errors.iter().filter(|error| !actual.contains(error)) {
+            failures.push(format!("Missing expected error: {}", missing));
+        }
+        for unexpected in actual.iter().filter(|line| !errors.contains(line)) {
+            failures.push(format!("Unexpected output on stderr: {}", unexpected));
+        }
+    }
+
+    let stdout: Vec<&str> = outcome.stdout.lines().collect();
+    for (i, expected_line) in expected.output.iter().enumerate() {
+        match stdout.get(i) {
+            Some(actual) if actual == expected_line => {}
+            Some(actual) => failures.push(format!(
+                "Expected output '{}' on line {} but got '{}'.",
+                expected_line,
+                i + 1,
+                actual
+            )),
+            None => failures.push(format!("Missing expected output '{}'.", expected_line)),
+        }
+    }
+    for extra in stdout.iter().skip(expected.output.len()) {
+        failures.push(format!("Got unexpected output '{}'.", extra));
+    }
+
+    failures
+}
@@ -1,12 +1,331 @@
 mod util;
 use assert_cmd::Command;
+use std::fs;
+use std::path::Path;
 
 #[test]
 fn test_main() {
-    let in_files = vec!["tests/data/1_hello.lox"];
+    let in_files = vec![
+        "tests/data/1_hello.lox",
+        "tests/data/2_scopes_and_vars.lox",
+        "tests/data/3_control_flow.lox",
+        "tests/data/4_functions.lox",
+    ];
 
     for in_file in in_files {
         let mut run_main = Command::cargo_bin("rlox").unwrap();
         run_main.arg(in_file).assert().success();
     }
 }
+
+#[test]
+fn test_script_output() {
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .arg("tests/data/4_functions.lox")
+        .assert()
+        .success()
+        .stdout("c should equal 3\n3\noutside\n")
+        .stderr("");
+}
+
+#[test]
+fn test_vm_backend() {
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .args(["--backend=vm", "tests/data/4_functions.lox"])
+        .assert()
+        .success()
+        .stdout("c should equal 3\n3\noutside\n")
+        .stderr("");
+
+    // Runtime errors are reported just as the tree-walking interpreter reports them
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .args([
+            "--backend=vm",
+            "--error-format=short",
+            "tests/data/runtime_error.lox",
+        ])
+        .assert()
+        .code(70)
+        .stdout("before\n")
+        .stderr("Operand must be a number.\n[line 4]\n");
+
+    // Tracing prints the stack and each instruction to stderr, leaving stdout to the program
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .args(["--backend=vm", "--trace-execution", "tests/data/trace.lox"])
+        .assert()
+        .success()
+        .stdout("3\n")
+        .stderr(util::read_file("trace.trace"));
+
+    // Logging prints a line to stderr for each collection, which stress makes happen often
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    let assert = run_main
+        .args(["--gc-stress", "--gc-log", "tests/data/4_functions.lox"])
+        .assert()
+        .success()
+        .stdout("c should equal 3\n3\noutside\n");
+    let log = String::from_utf8_lossy(&assert.get_output().stderr).into_owned();
+    assert!(log.lines().count() > 1);
+    assert!(log.lines().all(|line| line.starts_with("[gc ")));
+
+    // The REPL can't run on the VM
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main.arg("--backend=vm").assert().code(64);
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .args([
+            "--backend=tree",
+            "--trace-execution",
+            "tests/data/trace.lox",
+        ])
+        .assert()
+        .code(64);
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .args(["--backend=jit", "tests/data/1_hello.lox"])
+        .assert()
+        .code(64);
+}
+
+#[test]
+fn test_compiled_scripts() {
+    let compiled = Path::new(env!("CARGO_TARGET_TMPDIR")).join("4_functions.loxc");
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .args(["compile", "tests/data/4_functions.lox", "-o"])
+        .arg(&compiled)
+        .assert()
+        .success()
+        .stdout("");
+
+    // Compiled scripts run on the VM without needing the source
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .arg(&compiled)
+        .assert()
+        .success()
+        .stdout("c should equal 3\n3\noutside\n");
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .arg("--dump-bytecode")
+        .arg(&compiled)
+        .assert()
+        .success()
+        .stdout(util::read_file("4_functions.bytecode"));
+
+    // Files from other versions of the format are rejected
+    let mut bytes = fs::read(&compiled).unwrap();
+    bytes[5] += 1;
+    let other_version = compiled.with_file_name("other_version.loxc");
+    fs::write(&other_version, bytes).unwrap();
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .arg(&other_version)
+        .assert()
+        .code(65)
+        .stdout("")
+        .stderr(format!(
+            "Failed to load compiled script {}: Compiled with bytecode format version 2, \
+             but this rlox only runs version 1. Recompile it from source.\n",
+            other_version.display()
+        ));
+
+    // Scripts that don't compile aren't written
+    let not_written = compiled.with_file_name("compile_error.loxc");
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .args(["compile", "tests/data/compile_error.lox", "-o"])
+        .arg(&not_written)
+        .assert()
+        .code(65);
+    assert!(!not_written.exists());
+}
+
+#[test]
+fn test_exit_codes() {
+    // Too many arguments or an unknown option is a usage error
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main.args(["a.lox", "b.lox"]).assert().code(64);
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .args(["--error-format=xml", "a.lox"])
+        .assert()
+        .code(64);
+
+    // Compile errors are all reported and nothing is run
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .args(["--error-format=short", "tests/data/compile_error.lox"])
+        .assert()
+        .code(65)
+        .stdout("")
+        .stderr(
+            "[line 3] Error at ';': Expect expression.\n\
+             [line 4] Error at ';': Expect expression.\n",
+        );
+
+    // Runtime errors stop the program where they happen
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .args(["--error-format=short", "tests/data/runtime_error.lox"])
+        .assert()
+        .code(70)
+        .stdout("before\n")
+        .stderr("Operand must be a number.\n[line 4]\n");
+
+    // So does recursing too deeply, on either backend
+    for backend in ["--backend=tree", "--backend=vm"] {
+        let mut run_main = Command::cargo_bin("rlox").unwrap();
+        run_main
+            .args([
+                backend,
+                "--error-format=short",
+                "tests/data/stack_overflow.lox",
+            ])
+            .assert()
+            .code(70)
+            .stdout("before\n")
+            .stderr("Stack overflow.\n[line 3]\n");
+    }
+}
+
+#[test]
+fn test_human_errors() {
+    // Errors point at the source they came from by default
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .arg("tests/data/runtime_error.lox")
+        .assert()
+        .code(70)
+        .stderr(
+            "error[E0301]: Operand must be a number.\n \
+             --> tests/data/runtime_error.lox:4:7\n  \
+             |\n\
+             4 | print -greeting;\n  \
+             |       ^\n\n",
+        );
+}
+
+#[test]
+fn test_json_errors() {
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .args(["--error-format=json", "tests/data/compile_error.lox"])
+        .assert()
+        .code(65)
+        .stderr(
+            "{\"severity\":\"error\",\"code\":\"E0100\",\"message\":\"Expect expression.\",\
+             \"file\":\"tests/data/compile_error.lox\",\"start\":{\"line\":3,\"column\":9},\
+             \"end\":{\"line\":3,\"column\":10},\"notes\":[]}\n\
+             {\"severity\":\"error\",\"code\":\"E0100\",\"message\":\"Expect expression.\",\
+             \"file\":\"tests/data/compile_error.lox\",\"start\":{\"line\":4,\"column\":10},\
+             \"end\":{\"line\":4,\"column\":11},\"notes\":[]}\n",
+        );
+}
+
+#[test]
+fn test_repl_continuation() {
+    // Statements left open continue onto the next line, and definitions last the whole session
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .write_stdin("fun greet(name) {\n  print \"hi \" + name;\n}\ngreet(\"there\");\n")
+        .assert()
+        .success()
+        .stdout(
+            "[🦀 lox] [1] > [🦀 lox] [1] ... [🦀 lox] [1] ... \
+             [🦀 lox] [2] > hi there\n\
+             [🦀 lox] [3] > ",
+        );
+}
+
+#[test]
+fn test_repl_auto_print() {
+    // Lone expressions print their value, and a runtime error doesn't lose earlier definitions
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .write_stdin("var a = 1;\na + 2\n-nil\n\"a is \" + \"here\"\na\n")
+        .assert()
+        .success()
+        .stdout(
+            "[🦀 lox] [1] > [🦀 lox] [2] > => 3\n\
+             [🦀 lox] [3] > [🦀 lox] [4] > => a is here\n\
+             [🦀 lox] [5] > => 1\n\
+             [🦀 lox] [6] > ",
+        )
+        .stderr(
+            "error[E0301]: Operand must be a number.\n \
+             --> <repl>:1:1\n  \
+             |\n\
+             1 | -nil\n  \
+             | ^\n\n",
+        );
+}
+
+#[test]
+fn test_repl_stack_overflow() {
+    // Runaway recursion is reported like any other runtime error, and the session carries on
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .write_stdin("fun f(n) { return f(n + 1); } f(0);\nprint \"still here\";\n")
+        .assert()
+        .success()
+        .stdout("[🦀 lox] [1] > [🦀 lox] [2] > still here\n[🦀 lox] [3] > ")
+        .stderr(
+            "error[E0313]: Stack overflow.\n \
+             --> <repl>:1:26\n  \
+             |\n\
+             1 | fun f(n) { return f(n + 1); } f(0);\n  \
+             |                          ^\n  \
+             |\n  \
+             = help: check for recursion that never reaches a base case\n\n",
+        );
+}
+
+#[test]
+fn test_dump_modes() {
+    // Dumps are compared against golden files next to the scripts
+    let goldens = [
+        ("--dump-tokens", "1_hello.lox", "1_hello.tokens"),
+        ("--dump-ast", "4_functions.lox", "4_functions.ast"),
+        ("--dump-bytecode", "4_functions.lox", "4_functions.bytecode"),
+    ];
+    for (flag, script, golden) in goldens {
+        let mut run_main = Command::cargo_bin("rlox").unwrap();
+        run_main
+            .args([flag, &format!("tests/data/{}", script)])
+            .assert()
+            .success()
+            .stdout(util::read_file(golden))
+            .stderr("");
+    }
+
+    // Errors up to the stage dumped are reported, and later ones aren't looked for
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .args(["--dump-tokens", "tests/data/compile_error.lox"])
+        .assert()
+        .success();
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .args(["--dump-ast", "tests/data/compile_error.lox"])
+        .assert()
+        .code(65)
+        .stdout("");
+
+    // Scripts are never run
+    let mut run_main = Command::cargo_bin("rlox").unwrap();
+    run_main
+        .args(["--dump-ast", "tests/data/runtime_error.lox"])
+        .assert()
+        .success()
+        .stdout(
+            "(print \"before\")\n\
+             (var greeting \"hi\")\n\
+             (print (- greeting))\n\
+             (print \"after\")\n",
+        );
+}